@@ -0,0 +1,48 @@
+//! `w` 模块的基准测试：`cargo bench --bench w`
+//! 主 crate 只有二进制目标，与 fuzz 目录一样通过路径引入 w.rs
+
+// 基准测试只用到 w.rs 的一部分函数
+#![allow(dead_code)]
+
+#[path = "../src/entropy.rs"]
+mod entropy;
+#[path = "../fuzz/src/error.rs"]
+mod error;
+// harness = false 的目标由 cargo 以 `--cfg test` 而不是 `--test` 编译：
+// w.rs 的测试模块参与编译，其中的 #[test] 函数被剔除，只在测试函数中用到的导入因此未被使用
+#[allow(unused_imports)]
+#[path = "../src/w.rs"]
+mod w;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use entropy::Entropy;
+
+const CHALLENGE: &str = "0123456789abcdef0123456789abcdefgh";
+
+fn base64(c: &mut Criterion) {
+    let mut group = c.benchmark_group("base64");
+    // 实际的 w 中 AES 密文约 1.5 KB
+    for size in [1024, 4 * 1024, 16 * 1024] {
+        let data = (0..size).map(|i| (i * 31) as u8).collect::<Vec<_>>();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter(|| w::base64(black_box(data)))
+        });
+    }
+    group.finish();
+}
+
+fn generate_w(c: &mut Criterion) {
+    let entropy = Entropy::default();
+    c.bench_function("click_calculate", |b| {
+        b.iter(|| w::click_calculate("4231_5722,7023_2812", "gt", CHALLENGE, &entropy).unwrap())
+    });
+    c.bench_function("slide_calculate", |b| {
+        b.iter(|| {
+            w::slide_calculate(120, "gt", CHALLENGE, &[1, 2, 3, 4, 5], "ab", &entropy).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, base64, generate_w);
+criterion_main!(benches);
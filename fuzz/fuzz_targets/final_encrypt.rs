@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (String, Vec<u8>, String)| {
+    let (t, e, n) = input;
+    bili_ticket_gt_server_fuzz::final_encrypt(&t, &e, &n);
+});
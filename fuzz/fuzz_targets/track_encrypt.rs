@@ -0,0 +1,7 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|track: Vec<[i32; 3]>| {
+    bili_ticket_gt_server_fuzz::track_encrypt(&track);
+});
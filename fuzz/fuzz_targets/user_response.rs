@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (i16, String)| {
+    let (key, challenge) = input;
+    bili_ticket_gt_server_fuzz::user_response(key, &challenge);
+});
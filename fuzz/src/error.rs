@@ -0,0 +1,20 @@
+//! 主 crate 错误类型的替身，只提供 `w` 用到的构造函数，benches 也会引入本文件
+
+use std::fmt::Display;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub struct Error(String);
+
+pub(crate) fn parse_error<E: Display>(e: E) -> Error {
+    Error(format!("解析错误: {e}"))
+}
+
+pub(crate) fn other<E: Display>(s: &str, e: E) -> Error {
+    Error(format!("{s}: {e}"))
+}
+
+pub(crate) fn other_without_source(s: &str) -> Error {
+    Error(s.to_string())
+}
@@ -0,0 +1,12 @@
+//! 主 crate 只有二进制目标，这里通过路径引入 `w` 模块供 cargo-fuzz 目标调用。
+//! 入口函数见 `w::fuzz`，只在 `cfg(fuzzing)` 下编译。
+
+#![allow(dead_code)]
+
+#[path = "../../src/entropy.rs"]
+mod entropy;
+mod error;
+#[path = "../../src/w.rs"]
+mod w;
+
+pub use w::fuzz::*;
@@ -1,15 +1,177 @@
 // abstraction.rs
 
-use crate::error::{
-    missing_param, net_work_error, other, other_without_source, parse_error, Result,
-};
+use crate::egress::{self, Target};
+use crate::entropy::Entropy;
+use crate::error::{missing_param, other, other_without_source, parse_error, Result};
+use crate::transport::{fetch, Expected, Transport};
 use reqwest::blocking::Client;
+use serde::Serialize;
 use serde_json::Value;
-use std::collections::HashMap;
-// 修改：引入 SystemTime 和 UNIX_EPOCH 用于生成时间戳
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Instant;
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+/// ### 验证码图片地址
+/// - servers: 上游下发的全部图片服务器（如 `static.geetest.com/`），下载时按顺序回退
+/// - path: 图片在服务器上的路径
+#[derive(Clone, Debug)]
+pub(crate) struct ImageUrl {
+    pub(crate) servers: Vec<String>,
+    pub(crate) path: String,
+}
+
+impl ImageUrl {
+    /// 从接口响应中读取服务器列表和图片路径
+    pub(crate) fn from_response(data: &Value, servers_field: &str, path_field: &str) -> Result<Self> {
+        let servers = data
+            .get(servers_field)
+            .ok_or_else(|| missing_param(servers_field))?
+            .as_array()
+            .ok_or_else(|| missing_param(servers_field))?
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        if servers.is_empty() {
+            return Err(other_without_source(&format!("{servers_field}里面咋没东西啊")));
+        }
+        let path = data
+            .get(path_field)
+            .ok_or_else(|| missing_param(path_field))?
+            .as_str()
+            .ok_or_else(|| missing_param(path_field))?
+            .to_string();
+        Ok(Self { servers, path })
+    }
+
+    /// 按服务器顺序拼出的完整图片地址
+    pub(crate) fn urls(&self) -> impl Iterator<Item = String> + '_ {
+        self.servers.iter().map(|server| {
+            format!(
+                "https://{}/{}",
+                server.trim_end_matches('/'),
+                self.path.trim_start_matches('/')
+            )
+        })
+    }
+}
+
+/// ### 依次从各图片服务器下载
+/// - 每个服务器使用单独的超时，失败后记录日志并换下一个
+/// - 全部失败时返回最后一个服务器的错误
+pub(crate) fn download_with_fallback(
+    transport: &dyn Transport,
+    client: &Client,
+    image: &ImageUrl,
+) -> Result<Vec<u8>> {
+    let mut last_error = None;
+    for (attempt, url) in image.urls().enumerate() {
+        let result = egress::check(&url, Target::Image)
+            .and_then(|_| fetch(transport, client, &url, &[], Expected::Image));
+        let host = url.split('/').nth(2).unwrap_or_default();
+        match result {
+            Ok(res) => {
+                tracing::info!(host, attempt, bytes = res.body.len(), "验证码图片已下载");
+                return Ok(res.body);
+            }
+            Err(e) => {
+                tracing::warn!(host, attempt, error = %e, "图片服务器下载失败，尝试下一个");
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| other_without_source("没有可用的图片服务器")))
+}
+
+/// ### 极验验证接口的结果
+/// - Success: 通过验证，seccode 按极验前端的规则为 `{validate}|jordan`
+/// - Fail: 识别结果不正确，可以刷新图片后重试
+/// - Forbidden: 请求被极验判定为异常，重试同一 challenge 没有意义
+/// - ChallengeExpired: challenge 已失效，需要重新注册
+/// - Unknown: 无法识别的响应，原样保留
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub(crate) enum VerifyOutcome {
+    Success { validate: String, seccode: String },
+    Fail,
+    Forbidden,
+    ChallengeExpired,
+    Unknown { raw: Value },
+}
+
+impl VerifyOutcome {
+    /// ### 解析 ajax.php 的响应
+    /// - 点选的结果在 `data.result`，滑块的结果在顶层 `message`
+    /// - 出错时极验返回 `{"status": "error", "error": "..."}`
+    pub(crate) fn from_response(res: &Value) -> Self {
+        let data = res.get("data").unwrap_or(res);
+        let result = data
+            .get("result")
+            .or_else(|| data.get("message"))
+            .and_then(Value::as_str);
+        let validate = data.get("validate").and_then(Value::as_str);
+        match (result, validate) {
+            (Some("success"), Some(validate)) if !validate.is_empty() => VerifyOutcome::Success {
+                validate: validate.to_string(),
+                seccode: seccode(validate),
+            },
+            (Some("fail"), _) => VerifyOutcome::Fail,
+            (Some("forbidden"), _) => VerifyOutcome::Forbidden,
+            _ => {
+                let error = res.get("error").and_then(Value::as_str).unwrap_or_default();
+                if res.get("status").and_then(Value::as_str) == Some("error")
+                    && error.contains("challenge")
+                {
+                    VerifyOutcome::ChallengeExpired
+                } else {
+                    VerifyOutcome::Unknown { raw: res.clone() }
+                }
+            }
+        }
+    }
+}
+
+/// 极验前端提交表单时使用的 seccode
+pub(crate) fn seccode(validate: &str) -> String {
+    format!("{validate}|jordan")
+}
+
+/// ### 一次完整识别的各阶段耗时（毫秒）
+/// - prepare: 获取 c/s、验证码类型和图片参数，重试时包括刷新图片
+/// - recognize: 下载图片并识别出 key
+/// - generate_w / wait / verify: 生成 w、提交前的等待和提交验证
+/// - 重试时各阶段耗时累加，total 为整个调用的耗时
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct SolveTimings {
+    pub(crate) prepare_ms: u64,
+    pub(crate) recognize_ms: u64,
+    pub(crate) generate_w_ms: u64,
+    pub(crate) wait_ms: u64,
+    pub(crate) verify_ms: u64,
+    pub(crate) total_ms: u64,
+}
+
+impl SolveTimings {
+    /// 把从 started_at 到现在的耗时累加到某个阶段上
+    pub(crate) fn add(phase: &mut u64, started_at: Instant) {
+        *phase += started_at.elapsed().as_millis() as u64;
+    }
+}
+
+/// ### simple_match 系列接口的结果
+/// - challenge 为最终提交验证时使用的 challenge，滑块验证码会在获取图片时换新
+/// - key 为识别结果：点选为坐标序列，滑块为缺口距离
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct SolveResult {
+    pub(crate) captcha_type: VerifyType,
+    pub(crate) challenge: String,
+    pub(crate) validate: String,
+    pub(crate) seccode: String,
+    pub(crate) attempts: u32,
+    pub(crate) key: String,
+    pub(crate) timings: SolveTimings,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub(crate) enum VerifyType {
     Slide,
     Click,
@@ -23,9 +185,11 @@ pub(crate) trait Api {
     /// - gt
     /// - challenge
     fn register_test(&self, url: &str) -> Result<(String, String)> {
-        let res = self.client().get(url).send().map_err(net_work_error)?;
+        let _span = tracing::info_span!("register").entered();
+        egress::check(url, Target::Register)?;
+        let res = fetch(self.transport(), self.client(), url, &[], Expected::Json)?;
         // 改进：使用安全的错误处理替换 expect
-        let res = res.json::<Value>().map_err(parse_error)?;
+        let res: Value = serde_json::from_slice(&res.body).map_err(parse_error)?;
         Ok((
             res.get("gt")
                 .ok_or_else(|| missing_param("gt"))?
@@ -45,39 +209,13 @@ pub(crate) trait Api {
     /// - c
     /// - s
     fn get_c_s(&self, gt: &str, challenge: &str, w: Option<&str>) -> Result<(Vec<u8>, String)> {
-        // 修改：生成动态回调
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis()
-            .to_string();
-        let callback = format!("geetest_{}", timestamp);
-
+        let _span = tracing::info_span!("get_c_s").entered();
         let url = "https://api.geetest.com/get.php";
-        let mut params = HashMap::from([
-            ("gt", gt),
-            ("challenge", challenge),
-            ("callback", callback.as_str()), // 使用动态回调
-        ]);
+        let mut params = vec![("gt", gt), ("challenge", challenge)];
         if let Some(w) = w {
-            params.insert("w", w);
+            params.push(("w", w));
         }
-        let res = self
-            .client()
-            .get(url)
-            .query(&params)
-            .send()
-            .map_err(net_work_error)?;
-        let res = res.text().map_err(|e| other("什么b玩意错误", e))?;
-
-        // 修改：使用动态回调作为前缀
-        let prefix = format!("{}(", callback);
-        let res = res
-            .strip_prefix(&prefix)
-            .ok_or_else(|| other_without_source("前缀错误"))?
-            .strip_suffix(")")
-            .ok_or_else(|| other_without_source("后缀错误"))?;
-        let res: Value = serde_json::from_str(res).map_err(parse_error)?;
+        let res = self.jsonp(url, &params)?;
         let data = res.get("data").ok_or_else(|| missing_param("data"))?;
         let c: Vec<u8> =
             serde_json::from_value(data.get("c").ok_or_else(|| missing_param("c"))?.clone())
@@ -96,39 +234,13 @@ pub(crate) trait Api {
     /// #### 返回值
     /// - 验证码类型
     fn get_type(&self, gt: &str, challenge: &str, w: Option<&str>) -> Result<VerifyType> {
-        // 修改：生成动态回调
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis()
-            .to_string();
-        let callback = format!("geetest_{}", timestamp);
-
+        let _span = tracing::info_span!("get_type").entered();
         let url = "https://api.geetest.com/ajax.php";
-        let mut params = HashMap::from([
-            ("gt", gt),
-            ("challenge", challenge),
-            ("callback", callback.as_str()), // 使用动态回调
-        ]);
+        let mut params = vec![("gt", gt), ("challenge", challenge)];
         if let Some(w) = w {
-            params.insert("w", w);
+            params.push(("w", w));
         }
-        let res = self
-            .client()
-            .get(url)
-            .query(&params)
-            .send()
-            .map_err(net_work_error)?;
-        let res = res.text().map_err(|e| other("什么b玩意错误", e))?;
-
-        // 修改：使用动态回调作为前缀
-        let prefix = format!("{}(", callback);
-        let res = res
-            .strip_prefix(&prefix)
-            .ok_or_else(|| other_without_source("前缀错误"))?
-            .strip_suffix(")")
-            .ok_or_else(|| other_without_source("后缀错误"))?;
-        let res: Value = serde_json::from_str(res).map_err(parse_error)?;
+        let res = self.jsonp(url, &params)?;
         let data = res.get("data").ok_or_else(|| missing_param("data"))?;
         let result = data
             .get("result")
@@ -155,9 +267,8 @@ pub(crate) trait Api {
 
     /// ### 验证
     /// #### 返回值
-    /// - message
-    /// - validate
-    fn verify(&self, gt: &str, challenge: &str, w: Option<&str>) -> Result<(String, String)>;
+    /// - 验证结果，未通过时同样返回 Ok，由调用方决定是否重试
+    fn verify(&self, gt: &str, challenge: &str, w: Option<&str>) -> Result<VerifyOutcome>;
 
     /// ### 刷新
     /// #### 返回值
@@ -165,18 +276,37 @@ pub(crate) trait Api {
     fn refresh(&self, gt: &str, challenge: &str) -> Result<Self::ArgsType>;
 
     /// ### 下载图片
+    /// - 按上游下发的顺序依次尝试各图片服务器，单个服务器超时或出错时换下一个
     /// #### 返回值
     /// - img
-    fn download_img(&self, img_url: &str) -> Result<Vec<u8>> {
+    fn download_img(&self, image: &ImageUrl) -> Result<Vec<u8>> {
+        let _span = tracing::info_span!("download_image").entered();
         // 使用当前配置的图片下载客户端
-        let res = self
-            .download_client()
-            .get(img_url)
-            .send()
-            .map_err(net_work_error)?;
-        // 改进：使用安全的错误处理替换 unwrap
-        let bytes = res.bytes().map_err(net_work_error)?;
-        Ok(bytes.to_vec())
+        download_with_fallback(self.transport(), self.download_client(), image)
+    }
+
+    /// ### 请求极验 JSONP 接口
+    /// - 自动生成动态回调名并剥离 `callback(...)` 包装
+    /// #### 返回值
+    /// - 解析后的 JSON
+    fn jsonp(&self, url: &str, params: &[(&str, &str)]) -> Result<Value> {
+        // 修改：生成动态回调
+        let timestamp = self.entropy().clock.now_ms();
+        let callback = format!("geetest_{}", timestamp);
+
+        let mut query: Vec<(&str, &str)> = params.to_vec();
+        query.push(("callback", callback.as_str())); // 使用动态回调
+        let res = fetch(self.transport(), self.client(), url, &query, Expected::Jsonp)?;
+        let res = String::from_utf8(res.body).map_err(|e| other("什么b玩意错误", e))?;
+
+        // 修改：使用动态回调作为前缀
+        let prefix = format!("{}(", callback);
+        let res = res
+            .strip_prefix(&prefix)
+            .ok_or_else(|| other_without_source("前缀错误"))?
+            .strip_suffix(")")
+            .ok_or_else(|| other_without_source("后缀错误"))?;
+        serde_json::from_str(res).map_err(parse_error)
     }
 
     /// 返回可能带代理的客户端
@@ -184,6 +314,12 @@ pub(crate) trait Api {
 
     /// 返回当前用于下载图片的客户端，默认不带代理
     fn download_client(&self) -> &Client;
+
+    /// 返回发送上游请求的方式（直连、录制或回放）
+    fn transport(&self) -> &dyn Transport;
+
+    /// 返回生成 w 和回调名使用的随机数与时钟
+    fn entropy(&self) -> &Entropy;
 }
 
 pub(crate) trait GenerateW: Api {
@@ -205,3 +341,110 @@ pub(crate) trait Test: Api + GenerateW {
     /// ### 测试
     fn test(&mut self, url: &str) -> Result<String>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{upstream_error, verify_rejected};
+    use crate::transport::{Limits, UpstreamResponse};
+    use std::sync::Mutex;
+
+    /// 第一个图片服务器返回 503，其余服务器正常返回图片
+    struct FlakyServers {
+        requested: Mutex<Vec<String>>,
+    }
+
+    impl Transport for FlakyServers {
+        fn get(
+            &self,
+            _client: &Client,
+            url: &str,
+            _query: &[(&str, &str)],
+            _limits: Limits,
+        ) -> Result<UpstreamResponse> {
+            self.requested.lock().unwrap().push(url.to_string());
+            if url.contains("203.0.113.1") {
+                return Err(upstream_error(Some(503), url, "HTTP 状态码异常"));
+            }
+            Ok(UpstreamResponse {
+                status: 200,
+                content_type: Some("image/jpeg".to_string()),
+                body: b"jpeg".to_vec(),
+            })
+        }
+    }
+
+    #[test]
+    fn image_download_falls_back_to_next_server() {
+        let transport = FlakyServers {
+            requested: Mutex::new(Vec::new()),
+        };
+        let image = ImageUrl {
+            servers: vec!["203.0.113.1/".to_string(), "203.0.113.2".to_string()],
+            path: "/pictures/gt/a/b.jpg".to_string(),
+        };
+        let body = download_with_fallback(&transport, &Client::new(), &image).unwrap();
+        assert_eq!(body, b"jpeg");
+        assert_eq!(
+            *transport.requested.lock().unwrap(),
+            [
+                "https://203.0.113.1/pictures/gt/a/b.jpg",
+                "https://203.0.113.2/pictures/gt/a/b.jpg"
+            ]
+        );
+
+        let only_broken = ImageUrl {
+            servers: vec!["203.0.113.1".to_string()],
+            ..image
+        };
+        let err = download_with_fallback(&transport, &Client::new(), &only_broken).unwrap_err();
+        assert_eq!(err.upstream().and_then(|failure| failure.status), Some(503));
+    }
+
+    #[test]
+    fn verify_outcome_distinguishes_click_and_slide_responses() {
+        let click = serde_json::json!({"status": "success", "data": {"result": "success", "validate": "abc"}});
+        assert_eq!(
+            VerifyOutcome::from_response(&click),
+            VerifyOutcome::Success {
+                validate: "abc".to_string(),
+                seccode: "abc|jordan".to_string(),
+            }
+        );
+        let slide = serde_json::json!({"success": 0, "message": "fail"});
+        assert_eq!(VerifyOutcome::from_response(&slide), VerifyOutcome::Fail);
+        let forbidden = serde_json::json!({"status": "success", "data": {"result": "forbidden"}});
+        assert_eq!(VerifyOutcome::from_response(&forbidden), VerifyOutcome::Forbidden);
+        let expired = serde_json::json!({"status": "error", "error": "illegal challenge", "error_code": "error_02"});
+        assert_eq!(VerifyOutcome::from_response(&expired), VerifyOutcome::ChallengeExpired);
+        let odd = serde_json::json!({"status": "success", "data": {"result": "success"}});
+        assert_eq!(
+            VerifyOutcome::from_response(&odd),
+            VerifyOutcome::Unknown { raw: odd.clone() }
+        );
+
+        let err = verify_rejected(VerifyOutcome::from_response(&forbidden));
+        assert_eq!(err.code(), Some("VERIFY_FORBIDDEN"));
+        assert_eq!(
+            serde_json::to_value(VerifyOutcome::ChallengeExpired).unwrap(),
+            serde_json::json!({"outcome": "challenge_expired"})
+        );
+    }
+
+    #[test]
+    fn solve_result_serializes_the_same_shape_for_both_types() {
+        let result = SolveResult {
+            captcha_type: VerifyType::Slide,
+            challenge: "c2".to_string(),
+            validate: "v".to_string(),
+            seccode: seccode("v"),
+            attempts: 1,
+            key: "120".to_string(),
+            timings: SolveTimings::default(),
+        };
+        let value = serde_json::to_value(result).unwrap();
+        assert_eq!(value["captcha_type"], "slide");
+        assert_eq!(value["seccode"], "v|jordan");
+        assert_eq!(value["timings"]["verify_ms"], 0);
+    }
+}
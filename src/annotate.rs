@@ -0,0 +1,150 @@
+// annotate.rs
+
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+
+const MARKER: Rgba<u8> = Rgba([255, 0, 64, 255]);
+const OUTLINE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const LABEL_TEXT: Rgba<u8> = Rgba([255, 255, 255, 255]);
+/// 点选标记圆环的半径
+const MARKER_RADIUS: i64 = 9;
+/// 数字字形放大倍数
+const GLYPH_SCALE: i64 = 2;
+/// 叠加到背景图上的滑块透明度
+const PIECE_OPACITY: f32 = 0.75;
+
+/// 3x5 点阵数字，每行低 3 位从左到右
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+fn put(image: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>) {
+    if x >= 0 && y >= 0 && x < image.width() as i64 && y < image.height() as i64 {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+fn fill_rect(image: &mut RgbaImage, x: i64, y: i64, width: i64, height: i64, color: Rgba<u8>) {
+    for dy in 0..height {
+        for dx in 0..width {
+            put(image, x + dx, y + dy, color);
+        }
+    }
+}
+
+/// 圆心 (cx, cy)、半径 radius、线宽 thickness 的圆环
+fn ring(image: &mut RgbaImage, cx: i64, cy: i64, radius: i64, thickness: i64, color: Rgba<u8>) {
+    let outer = radius * radius;
+    let inner = (radius - thickness).max(0).pow(2);
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            let distance = dx * dx + dy * dy;
+            if distance <= outer && distance > inner {
+                put(image, cx + dx, cy + dy, color);
+            }
+        }
+    }
+}
+
+/// 在 (x, y) 处写出带底色的数字
+fn label(image: &mut RgbaImage, x: i64, y: i64, number: usize, background: Rgba<u8>) {
+    let text = number.to_string();
+    let advance = 4 * GLYPH_SCALE;
+    let width = text.len() as i64 * advance + GLYPH_SCALE;
+    fill_rect(image, x, y, width, 7 * GLYPH_SCALE, background);
+    for (index, digit) in (0i64..).zip(text.bytes()) {
+        let glyph = DIGITS[(digit - b'0') as usize];
+        let left = x + GLYPH_SCALE + index * advance;
+        for (row, bits) in (0i64..).zip(glyph) {
+            for column in 0..3 {
+                if bits & (0b100 >> column) != 0 {
+                    fill_rect(
+                        image,
+                        left + column * GLYPH_SCALE,
+                        y + GLYPH_SCALE + row * GLYPH_SCALE,
+                        GLYPH_SCALE,
+                        GLYPH_SCALE,
+                        LABEL_TEXT,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// ### 点选识别结果标注图
+/// - 在原图上按点击顺序标出识别到的坐标，编号从 1 开始
+/// - points 为模型输出的像素坐标，与换算成 key 之前的值一致
+pub(crate) fn click(picture: &DynamicImage, points: &[(f32, f32)]) -> DynamicImage {
+    let mut image = picture.to_rgba8();
+    for (index, &(x, y)) in points.iter().enumerate() {
+        let (cx, cy) = (x.round() as i64, y.round() as i64);
+        ring(&mut image, cx, cy, MARKER_RADIUS + 1, 4, OUTLINE);
+        ring(&mut image, cx, cy, MARKER_RADIUS, 2, MARKER);
+        fill_rect(&mut image, cx - 1, cy - 1, 3, 3, MARKER);
+        label(
+            &mut image,
+            cx + MARKER_RADIUS,
+            cy - MARKER_RADIUS - 7 * GLYPH_SCALE,
+            index + 1,
+            MARKER,
+        );
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+/// ### 滑块识别结果标注图
+/// - 在还原后的背景图上按识别出的 x1 半透明放置滑块，并画出 x1 所在的竖线
+pub(crate) fn slide(background: &DynamicImage, piece: &DynamicImage, x1: u32) -> DynamicImage {
+    let mut image = background.to_rgba8();
+    let mut piece = piece.to_rgba8();
+    for pixel in piece.pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * PIECE_OPACITY).round() as u8;
+    }
+    imageops::overlay(&mut image, &piece, x1 as i64, 0);
+
+    let (x, height) = (x1 as i64, image.height() as i64);
+    fill_rect(&mut image, x, 0, 1, height, MARKER);
+    label(&mut image, x + 2, 0, x1 as usize, MARKER);
+    DynamicImage::ImageRgba8(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_click_points_and_slide_offset() {
+        let picture = DynamicImage::new_rgba8(100, 100);
+        let image = click(&picture, &[(30.0, 60.0), (70.4, 20.6)]).to_rgba8();
+        assert_eq!(*image.get_pixel(30, 60), MARKER);
+        assert_eq!(*image.get_pixel(70, 21), MARKER);
+        assert_eq!(*image.get_pixel(30 + MARKER_RADIUS as u32 - 1, 60), MARKER);
+        // 编号标签画在标记右上方，数字 1 的竖笔为白色
+        let label_y = 60 - MARKER_RADIUS as u32 - 7 * GLYPH_SCALE as u32;
+        let stroke = (
+            30 + MARKER_RADIUS as u32 + 2 * GLYPH_SCALE as u32,
+            label_y + 4 * GLYPH_SCALE as u32,
+        );
+        assert_eq!(*image.get_pixel(stroke.0, stroke.1), LABEL_TEXT);
+        assert_eq!(*image.get_pixel(5, 5), Rgba([0, 0, 0, 0]));
+
+        let background =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(260, 160, Rgba([0, 0, 0, 255])));
+        let mut piece = RgbaImage::new(60, 160);
+        fill_rect(&mut piece, 10, 50, 40, 40, Rgba([0, 200, 0, 255]));
+        let image = slide(&background, &DynamicImage::ImageRgba8(piece), 120).to_rgba8();
+        assert_eq!(*image.get_pixel(120, 100), MARKER);
+        // 滑块不透明部分按透明度混合，透明部分保持背景
+        assert!((140..160).contains(&image.get_pixel(140, 70)[1]));
+        assert_eq!(*image.get_pixel(140, 20), Rgba([0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,1130 @@
+// api.rs
+
+use crate::abstraction::{SolveResult, Test, VerifyOutcome, VerifyType};
+use crate::click::Click;
+use crate::manual::Answer;
+use crate::slide::Slide;
+use crate::solve::{Flow, Solve, SolveFlow, Step};
+use crate::transport::Transport;
+use crate::{current_request_id, debug, error, recognizer, AppState};
+use axum::{
+    extract::{FromRequest, Path, Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::post,
+    Router,
+};
+use lru::LruCache;
+use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::task;
+
+/// 连接相关的公共参数，决定使用哪个会话实例和上游客户端
+#[derive(Clone, Default, Deserialize)]
+pub(crate) struct ConnectionOptions {
+    session_id: Option<String>,
+    proxy: Option<String>,
+    image_use_proxy: Option<bool>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+}
+
+// 统一请求结构体
+#[derive(Deserialize)]
+struct CommonRequest {
+    gt: String,
+    challenge: String,
+    w: Option<String>,
+    #[serde(flatten)]
+    options: ConnectionOptions,
+}
+
+#[derive(Deserialize)]
+struct UrlRequest {
+    url: String,
+    #[serde(flatten)]
+    options: ConnectionOptions,
+}
+
+#[derive(Deserialize)]
+struct GenerateWRequest {
+    key: String,
+    gt: String,
+    challenge: String,
+    c: Vec<u8>,
+    s: String,
+    #[serde(flatten)]
+    options: ConnectionOptions,
+}
+
+impl CommonRequest {
+    /// 调试模式下写入 meta.json 的请求参数，不包含代理等连接配置
+    fn debug_inputs(&self) -> Value {
+        json!({ "gt": self.gt, "challenge": self.challenge, "w": self.w })
+    }
+}
+
+impl UrlRequest {
+    fn debug_inputs(&self) -> Value {
+        json!({ "url": self.url })
+    }
+}
+
+impl GenerateWRequest {
+    fn debug_inputs(&self) -> Value {
+        json!({
+            "key": self.key,
+            "gt": self.gt,
+            "challenge": self.challenge,
+            "c": self.c,
+            "s": self.s,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ApiResponse<T> {
+    success: bool,
+    data: Option<T>,
+    error: Option<String>,
+    /// 需要调用方区分处理的错误码，例如 `EGRESS_BLOCKED`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    /// 上游异常时的状态码、主机和请求路径
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream: Option<error::UpstreamFailure>,
+    request_id: Option<String>,
+}
+
+impl<T> ApiResponse<T> {
+    pub(crate) fn success(data: T) -> Self {
+        Self {
+            success: true,
+            data: Some(data),
+            error: None,
+            code: None,
+            upstream: None,
+            request_id: current_request_id(),
+        }
+    }
+    pub(crate) fn error(message: String) -> Self {
+        Self {
+            success: false,
+            data: None,
+            error: Some(message),
+            code: None,
+            upstream: None,
+            request_id: current_request_id(),
+        }
+    }
+}
+
+/// ### 响应风格
+/// - Legacy: 未带版本号的旧接口，保持原有的数据结构，只在需要区分处理的错误上带错误码
+/// - V1: `/v1` 接口，数据使用具名字段，所有错误都带错误码
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Style {
+    Legacy,
+    V1,
+}
+
+impl Style {
+    fn fallback_code(self, code: &'static str) -> Option<&'static str> {
+        match self {
+            Style::Legacy => None,
+            Style::V1 => Some(code),
+        }
+    }
+}
+
+fn error_response(status: StatusCode, message: String, code: Option<&'static str>) -> Response {
+    let body = ApiResponse::<()> {
+        code,
+        ..ApiResponse::error(message)
+    };
+    (status, Json(body)).into_response()
+}
+
+/// ### 业务错误响应
+/// - 被出站策略拦截返回 403，上游异常返回 502，未编译识别模型返回 501，分块方式错误返回 500，并带上错误码
+/// - 其余业务错误返回 400
+fn business_error_response(e: &error::Error, style: Style) -> Response {
+    let status = match e.code() {
+        Some(error::EGRESS_BLOCKED) => StatusCode::FORBIDDEN,
+        Some(error::UPSTREAM_ERROR | error::IMAGE_SIZE_MISMATCH) => StatusCode::BAD_GATEWAY,
+        Some(error::RECOGNITION_NOT_COMPILED) => StatusCode::NOT_IMPLEMENTED,
+        Some(error::INVALID_LAYOUT) => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    let body = ApiResponse::<()> {
+        code: e.code().or(style.fallback_code(error::REQUEST_FAILED)),
+        upstream: e.upstream().cloned(),
+        ..ApiResponse::error(e.to_string())
+    };
+    (status, Json(body)).into_response()
+}
+
+fn unsupported(captcha_type: &str, operation: &str, style: Style) -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        format!("不支持的接口: {captcha_type}/{operation}"),
+        style.fallback_code(error::UNSUPPORTED_OPERATION),
+    )
+}
+
+/// HTTP 接口提供的操作
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Operation {
+    SimpleMatch,
+    SimpleMatchRetry,
+    RegisterTest,
+    GetCS,
+    GetType,
+    Verify,
+    GenerateW,
+    Test,
+}
+
+impl Operation {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "simple_match" => Some(Operation::SimpleMatch),
+            "simple_match_retry" => Some(Operation::SimpleMatchRetry),
+            "register_test" => Some(Operation::RegisterTest),
+            "get_c_s" => Some(Operation::GetCS),
+            "get_type" => Some(Operation::GetType),
+            "verify" => Some(Operation::Verify),
+            "generate_w" => Some(Operation::GenerateW),
+            "test" => Some(Operation::Test),
+            _ => None,
+        }
+    }
+
+    /// 需要识别图片的操作
+    fn recognizes(self) -> bool {
+        matches!(
+            self,
+            Operation::SimpleMatch | Operation::SimpleMatchRetry | Operation::Test
+        )
+    }
+}
+
+/// ### 可以通过 HTTP 接口调用的验证码类型
+/// - 统一会话实例的创建，识别流程见 `Solve`，其余操作直接使用 `Api`/`GenerateW`
+pub(crate) trait Solver: Solve + Test + Clone {
+    /// 录制目录使用的类型名
+    const NAME: &'static str;
+    /// 支持的操作，其余操作返回 404
+    const OPERATIONS: &'static [Operation];
+
+    fn new(client: Arc<Client>, download_client: Arc<Client>) -> Self;
+    fn update_clients(&mut self, client: Arc<Client>, download_client: Arc<Client>);
+    fn set_transport(&mut self, transport: Arc<dyn Transport>);
+    /// 按会话缓存的实例
+    fn instances(state: &AppState) -> &Mutex<LruCache<String, Self>>;
+}
+
+impl Solver for Click {
+    const NAME: &'static str = "click";
+    const OPERATIONS: &'static [Operation] = &[
+        Operation::SimpleMatch,
+        Operation::SimpleMatchRetry,
+        Operation::RegisterTest,
+        Operation::GetCS,
+        Operation::GetType,
+        Operation::Verify,
+        Operation::GenerateW,
+        Operation::Test,
+    ];
+
+    fn new(client: Arc<Client>, download_client: Arc<Client>) -> Self {
+        Click::new(client, download_client)
+    }
+    fn update_clients(&mut self, client: Arc<Client>, download_client: Arc<Client>) {
+        Click::update_clients(self, client, download_client)
+    }
+    fn set_transport(&mut self, transport: Arc<dyn Transport>) {
+        Click::set_transport(self, transport)
+    }
+    fn instances(state: &AppState) -> &Mutex<LruCache<String, Self>> {
+        &state.click_instances
+    }
+}
+
+impl Solver for Slide {
+    const NAME: &'static str = "slide";
+    const OPERATIONS: &'static [Operation] = &[
+        Operation::SimpleMatch,
+        Operation::RegisterTest,
+        Operation::GetCS,
+        Operation::GetType,
+        Operation::Verify,
+        Operation::GenerateW,
+        Operation::Test,
+    ];
+
+    fn new(client: Arc<Client>, download_client: Arc<Client>) -> Self {
+        Slide::new(client, download_client)
+    }
+    fn update_clients(&mut self, client: Arc<Client>, download_client: Arc<Client>) {
+        Slide::update_clients(self, client, download_client)
+    }
+    fn set_transport(&mut self, transport: Arc<dyn Transport>) {
+        Slide::set_transport(self, transport)
+    }
+    fn instances(state: &AppState) -> &Mutex<LruCache<String, Self>> {
+        &state.slide_instances
+    }
+}
+
+/// ### 取出会话对应的实例
+/// - 同一会话复用实例，每次请求按参数更新客户端
+/// - 须在阻塞线程中调用，reqwest 的阻塞客户端不能在异步上下文中创建
+fn session_instance<T: Solver>(state: &AppState, options: ConnectionOptions) -> Result<T, String> {
+    let session_id = options.session_id.unwrap_or_else(|| "default".to_string());
+    let client = state
+        .client_manager
+        .get(
+            options.proxy.as_deref(),
+            options.user_agent.as_deref(),
+            options.referer.as_deref(),
+        )
+        .map_err(|e| e.to_string())?;
+    let download_client = if options.image_use_proxy.unwrap_or(false) {
+        Arc::clone(&client)
+    } else {
+        state
+            .client_manager
+            .get(None, None, None)
+            .map_err(|e| e.to_string())?
+    };
+    let mut instance = {
+        let mut instances = T::instances(state)
+            .lock()
+            .map_err(|_| "内部服务错误: Mutex poisoned".to_string())?;
+        if let Some(instance) = instances.get_mut(&session_id) {
+            instance.update_clients(Arc::clone(&client), Arc::clone(&download_client));
+            instance.clone()
+        } else {
+            let instance = T::new(client, download_client);
+            instances.put(session_id, instance.clone());
+            instance
+        }
+    };
+    if let Some(transport) = state.recording_transport(T::NAME) {
+        instance.set_transport(transport);
+    }
+    Ok(instance)
+}
+
+/// 各操作的结果，按响应风格转换为 JSON
+enum Output {
+    Registered { gt: String, challenge: String },
+    CS { c: Vec<u8>, s: String },
+    Type(VerifyType),
+    Verified(VerifyOutcome),
+    W(String),
+    Validate(String),
+    Solved(SolveResult),
+}
+
+impl Output {
+    fn render(self, style: Style) -> Value {
+        match (self, style) {
+            (Output::Registered { gt, challenge }, Style::Legacy) => {
+                json!({ "first": gt, "second": challenge })
+            }
+            (Output::Registered { gt, challenge }, Style::V1) => {
+                json!({ "gt": gt, "challenge": challenge })
+            }
+            (Output::CS { c, s }, _) => json!({ "c": c, "s": s }),
+            (Output::Type(captcha_type), Style::Legacy) => json!(captcha_type),
+            (Output::Type(captcha_type), Style::V1) => json!({ "captcha_type": captcha_type }),
+            (Output::Verified(outcome), _) => json!(outcome),
+            (Output::W(w), Style::Legacy) => json!(w),
+            (Output::W(w), Style::V1) => json!({ "w": w }),
+            (Output::Validate(validate), Style::Legacy) => json!(validate),
+            (Output::Validate(validate), Style::V1) => json!({ "validate": validate }),
+            (Output::Solved(result), _) => json!(result),
+        }
+    }
+}
+
+/// 阻塞任务失败的原因，对应不同的 HTTP 状态码
+enum Failure {
+    Instance(String),
+    Business(error::Error),
+    Panic(Box<dyn Any + Send>),
+    Join(task::JoinError),
+}
+
+/// 只有一段阻塞操作的流程
+struct Single<I, F> {
+    instance: I,
+    operation: Option<F>,
+}
+
+impl<I, F> Flow for Single<I, F>
+where
+    F: FnOnce(&mut I) -> error::Result<Output>,
+{
+    type Output = Output;
+
+    fn advance(&mut self) -> error::Result<Step<Output>> {
+        let operation = self
+            .operation
+            .take()
+            .ok_or_else(|| error::other_without_source("操作已执行"))?;
+        operation(&mut self.instance).map(Step::Done)
+    }
+}
+
+/// 取出会话实例并包装为只有一段的流程
+fn single<T: Solver, F>(
+    state: AppState,
+    options: ConnectionOptions,
+    operation: F,
+) -> impl FnOnce() -> Result<Single<T, F>, String> + Send + 'static
+where
+    F: FnOnce(&mut T) -> error::Result<Output> + Send + 'static,
+{
+    move || {
+        Ok(Single {
+            instance: session_instance(&state, options)?,
+            operation: Some(operation),
+        })
+    }
+}
+
+/// 把识别流程的结果转换为接口输出
+struct Solved<F>(F);
+
+impl<F: Flow<Output = SolveResult>> Flow for Solved<F> {
+    type Output = Output;
+
+    fn advance(&mut self) -> error::Result<Step<Output>> {
+        Ok(match self.0.advance()? {
+            Step::Wait(until) => Step::Wait(until),
+            Step::Parked(parked) => Step::Parked(parked),
+            Step::Done(result) => Step::Done(Output::Solved(result)),
+        })
+    }
+
+    fn resume(&mut self, answer: Option<Answer>) {
+        self.0.resume(answer)
+    }
+}
+
+/// 在阻塞线程中执行一段操作，带上当前 span 并捕获 panic
+async fn blocking<R: Send + 'static>(
+    f: impl FnOnce() -> Result<R, Failure> + Send + 'static,
+) -> Result<R, Failure> {
+    // 阻塞线程不会继承当前 span，需要手动带过去
+    let span = tracing::Span::current();
+    task::spawn_blocking(move || {
+        let _entered = span.enter();
+        panic::catch_unwind(AssertUnwindSafe(f)).map_err(Failure::Panic)?
+    })
+    .await
+    .map_err(Failure::Join)?
+}
+
+/// ### 执行流程直到结束
+/// - 每段都在阻塞线程中执行，段与段之间的等待（如提交前的 2 秒、人工识别）都是异步的，不占用线程
+/// - 调试作用域随流程在线程间传递，panic 时作用域被丢弃并记为 panic
+async fn drive<F, B>(mut scope: debug::RequestScope, build: B) -> Result<Output, Failure>
+where
+    B: FnOnce() -> Result<F, String> + Send + 'static,
+    F: Flow<Output = Output> + Send + 'static,
+{
+    let mut flow = blocking(move || build().map_err(Failure::Instance)).await?;
+    loop {
+        let (returned_scope, returned_flow, step) = blocking(move || {
+            let step = scope.enter(|| flow.advance());
+            Ok((scope, flow, step))
+        })
+        .await?;
+        scope = returned_scope;
+        flow = returned_flow;
+        match step {
+            Ok(Step::Wait(until)) => tokio::time::sleep_until(until.into()).await,
+            Ok(Step::Parked(parked)) => {
+                // 人工答复可能要等几分钟，在异步任务中等待，不占用阻塞线程
+                let answer = tokio::time::timeout(parked.timeout, parked.receiver).await;
+                flow.resume(answer.ok().and_then(Result::ok));
+            }
+            Ok(Step::Done(output)) => {
+                scope.finish(None);
+                return Ok(output);
+            }
+            Err(e) => {
+                scope.finish(Some(&e));
+                return Err(Failure::Business(e));
+            }
+        }
+    }
+}
+
+/// ### 执行一个操作
+/// - build 在阻塞线程中取出会话实例并构造流程
+/// - debug 取自 `AppState`，为 true 时记录本次请求的调试产物
+/// - 业务逻辑中的 panic 被捕获并返回 500
+async fn run<F, B>(debug: bool, endpoint: String, inputs: Value, style: Style, build: B) -> Response
+where
+    B: FnOnce() -> Result<F, String> + Send + 'static,
+    F: Flow<Output = Output> + Send + 'static,
+{
+    let request_id = current_request_id().unwrap_or_else(debug::new_request_id);
+    let scope = debug::RequestScope::new(debug, &request_id, &endpoint, inputs);
+    match drive(scope, build).await {
+        Ok(output) => Json(ApiResponse::success(output.render(style))).into_response(),
+        Err(Failure::Business(e)) => {
+            tracing::error!("业务逻辑错误: {}", e);
+            business_error_response(&e, style)
+        }
+        Err(Failure::Instance(message)) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            message,
+            style.fallback_code(error::INTERNAL_ERROR),
+        ),
+        Err(Failure::Panic(payload)) => {
+            tracing::error!(panic_payload = ?payload, "阻塞业务任务发生 panic");
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "内部服务错误: 阻塞任务 panic".to_string(),
+                style.fallback_code(error::INTERNAL_ERROR),
+            )
+        }
+        Err(Failure::Join(e)) => {
+            tracing::error!("Tokio 任务执行错误: {}", e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                e.to_string(),
+                style.fallback_code(error::INTERNAL_ERROR),
+            )
+        }
+    }
+}
+
+/// 解析请求体，旧接口保持 axum 原有的拒绝响应
+async fn body<B: DeserializeOwned>(req: Request, style: Style) -> Result<B, Response> {
+    match Json::<B>::from_request(req, &()).await {
+        Ok(Json(body)) => Ok(body),
+        Err(rejection) => Err(match style {
+            Style::Legacy => rejection.into_response(),
+            Style::V1 => error_response(
+                rejection.status(),
+                rejection.body_text(),
+                Some(error::INVALID_REQUEST),
+            ),
+        }),
+    }
+}
+
+macro_rules! parse_body {
+    ($req:expr, $style:expr) => {
+        match body($req, $style).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        }
+    };
+}
+
+/// simple_match_retry 最多提交验证的次数
+const MAX_RETRY_ATTEMPTS: u32 = 5;
+
+/// 执行单个验证码类型的一个操作
+async fn dispatch<T: Solver>(
+    state: AppState,
+    operation: Operation,
+    endpoint: String,
+    style: Style,
+    req: Request,
+) -> Response {
+    let debug = state.debug;
+    if operation.recognizes() {
+        if let Err(e) = recognizer::ensure_available() {
+            return business_error_response(&e, style);
+        }
+    }
+    match operation {
+        Operation::SimpleMatch | Operation::SimpleMatchRetry => {
+            let req: CommonRequest = parse_body!(req, style);
+            let inputs = req.debug_inputs();
+            let max_attempts = if operation == Operation::SimpleMatchRetry {
+                MAX_RETRY_ATTEMPTS
+            } else {
+                1
+            };
+            let build = move || {
+                let solver = session_instance::<T>(&state, req.options)?;
+                let flow = SolveFlow::new(solver, &req.gt, &req.challenge).retry(max_attempts);
+                Ok(Solved(flow))
+            };
+            run(debug, endpoint, inputs, style, build).await
+        }
+        Operation::RegisterTest => {
+            let req: UrlRequest = parse_body!(req, style);
+            let inputs = req.debug_inputs();
+            let build = single(state, req.options, move |instance: &mut T| {
+                let (gt, challenge) = instance.register_test(&req.url)?;
+                Ok(Output::Registered { gt, challenge })
+            });
+            run(debug, endpoint, inputs, style, build).await
+        }
+        Operation::GetCS => {
+            let req: CommonRequest = parse_body!(req, style);
+            let inputs = req.debug_inputs();
+            let build = single(state, req.options, move |instance: &mut T| {
+                let (c, s) = instance.get_c_s(&req.gt, &req.challenge, req.w.as_deref())?;
+                Ok(Output::CS { c, s })
+            });
+            run(debug, endpoint, inputs, style, build).await
+        }
+        Operation::GetType => {
+            let req: CommonRequest = parse_body!(req, style);
+            let inputs = req.debug_inputs();
+            let build = single(state, req.options, move |instance: &mut T| {
+                instance
+                    .get_type(&req.gt, &req.challenge, req.w.as_deref())
+                    .map(Output::Type)
+            });
+            run(debug, endpoint, inputs, style, build).await
+        }
+        Operation::Verify => {
+            let req: CommonRequest = parse_body!(req, style);
+            let inputs = req.debug_inputs();
+            let build = single(state, req.options, move |instance: &mut T| {
+                instance
+                    .verify(&req.gt, &req.challenge, req.w.as_deref())
+                    .map(Output::Verified)
+            });
+            run(debug, endpoint, inputs, style, build).await
+        }
+        Operation::GenerateW => {
+            let req: GenerateWRequest = parse_body!(req, style);
+            let inputs = req.debug_inputs();
+            let build = single(state, req.options, move |instance: &mut T| {
+                instance
+                    .generate_w(&req.key, &req.gt, &req.challenge, &req.c, &req.s)
+                    .map(Output::W)
+            });
+            run(debug, endpoint, inputs, style, build).await
+        }
+        Operation::Test => {
+            let req: UrlRequest = parse_body!(req, style);
+            let inputs = req.debug_inputs();
+            let build = move || {
+                let solver = session_instance::<T>(&state, req.options)?;
+                Ok(Tested(SolveFlow::register(solver, &req.url)))
+            };
+            run(debug, endpoint, inputs, style, build).await
+        }
+    }
+}
+
+/// test 接口只返回 validate
+struct Tested<F>(F);
+
+impl<F: Flow<Output = SolveResult>> Flow for Tested<F> {
+    type Output = Output;
+
+    fn advance(&mut self) -> error::Result<Step<Output>> {
+        Ok(match self.0.advance()? {
+            Step::Wait(until) => Step::Wait(until),
+            Step::Parked(parked) => Step::Parked(parked),
+            Step::Done(result) => Step::Done(Output::Validate(result.validate)),
+        })
+    }
+
+    fn resume(&mut self, answer: Option<Answer>) {
+        self.0.resume(answer)
+    }
+}
+
+/// ### 自动识别验证码类型的流程
+/// - get_c_s 和 get_type 只调用一次，再交给点选或滑块流程继续
+/// - 服务中 C、S 为 `Click` 和 `Slide`
+enum AutoFlow<C: Solve, S: Solve> {
+    Detect {
+        click: C,
+        slide: S,
+        gt: String,
+        challenge: String,
+    },
+    Click(SolveFlow<C>),
+    Slide(SolveFlow<S>),
+}
+
+impl<C: Solve + Clone, S: Solve + Clone> Flow for AutoFlow<C, S> {
+    type Output = SolveResult;
+
+    fn advance(&mut self) -> error::Result<Step<SolveResult>> {
+        match self {
+            AutoFlow::Detect {
+                click,
+                slide,
+                gt,
+                challenge,
+            } => {
+                let started_at = Instant::now();
+                click.get_c_s(gt, challenge, None)?;
+                let verify_type = click.get_type(gt, challenge, None)?;
+                tracing::info!(verify_type = ?verify_type, "已识别验证码类型");
+                *self = match verify_type {
+                    VerifyType::Click => AutoFlow::Click(
+                        SolveFlow::new(click.clone(), gt, challenge).detected(started_at),
+                    ),
+                    VerifyType::Slide => AutoFlow::Slide(
+                        SolveFlow::new(slide.clone(), gt, challenge).detected(started_at),
+                    ),
+                };
+                self.advance()
+            }
+            AutoFlow::Click(flow) => flow.advance(),
+            AutoFlow::Slide(flow) => flow.advance(),
+        }
+    }
+    fn resume(&mut self, answer: Option<Answer>) {
+        match self {
+            AutoFlow::Detect { .. } => {}
+            AutoFlow::Click(flow) => flow.resume(answer),
+            AutoFlow::Slide(flow) => flow.resume(answer),
+        }
+    }
+}
+
+/// `auto` 类型只支持 simple_match
+async fn dispatch_auto(state: AppState, endpoint: String, style: Style, req: Request) -> Response {
+    let debug = state.debug;
+    if let Err(e) = recognizer::ensure_available() {
+        return business_error_response(&e, style);
+    }
+    let req: CommonRequest = parse_body!(req, style);
+    let inputs = req.debug_inputs();
+    // 同一会话的点选和滑块实例都要取出，识别出类型后再决定使用哪个
+    let build = move || {
+        let click = session_instance::<Click>(&state, req.options.clone())?;
+        let slide = session_instance::<Slide>(&state, req.options)?;
+        Ok(Solved(AutoFlow::Detect {
+            click,
+            slide,
+            gt: req.gt,
+            challenge: req.challenge,
+        }))
+    };
+    run(debug, endpoint, inputs, style, build).await
+}
+
+async fn handle(
+    state: AppState,
+    captcha_type: String,
+    operation: String,
+    style: Style,
+    req: Request,
+) -> Response {
+    let endpoint = req.uri().path().to_string();
+    let parsed = Operation::parse(&operation);
+    match (captcha_type.as_str(), parsed) {
+        ("click", Some(op)) if Click::OPERATIONS.contains(&op) => {
+            dispatch::<Click>(state, op, endpoint, style, req).await
+        }
+        ("slide", Some(op)) if Slide::OPERATIONS.contains(&op) => {
+            dispatch::<Slide>(state, op, endpoint, style, req).await
+        }
+        ("auto", Some(Operation::SimpleMatch)) => dispatch_auto(state, endpoint, style, req).await,
+        _ => unsupported(&captcha_type, &operation, style),
+    }
+}
+
+/// `/v1/{captcha_type}/{operation}`
+async fn v1(
+    State(state): State<AppState>,
+    Path((captcha_type, operation)): Path<(String, String)>,
+    req: Request,
+) -> Response {
+    handle(state, captcha_type, operation, Style::V1, req).await
+}
+
+/// 未带版本号的旧接口，保持原有的响应结构
+async fn legacy(
+    State(state): State<AppState>,
+    Path((captcha_type, operation)): Path<(String, String)>,
+    req: Request,
+) -> Response {
+    handle(state, captcha_type, operation, Style::Legacy, req).await
+}
+
+/// 验证码相关的全部接口
+pub(crate) fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/:captcha_type/:operation", post(v1))
+        .route("/:captcha_type/:operation", post(legacy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manual::ManualRecognizer;
+    use crate::solve::testing::Scripted;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{header, HeaderMap};
+    use std::time::Duration;
+    use tower::Service;
+
+    async fn call(
+        state: AppState,
+        request: axum::http::Request<Body>,
+    ) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let response = crate::app(state).call(request).await.unwrap();
+        let (status, headers) = (response.status(), response.headers().clone());
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, headers, bytes.to_vec())
+    }
+
+    async fn send(request: axum::http::Request<Body>) -> (StatusCode, HeaderMap, Value) {
+        send_to(AppState::new(None), request).await
+    }
+
+    async fn send_to(
+        state: AppState,
+        request: axum::http::Request<Body>,
+    ) -> (StatusCode, HeaderMap, Value) {
+        let (status, headers, bytes) = call(state, request).await;
+        (
+            status,
+            headers,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    fn json_request(path: &str, body: &Value) -> axum::http::Request<Body> {
+        axum::http::Request::post(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn post_json(path: &str, body: Value) -> (StatusCode, Value) {
+        let (status, _, body) = send(json_request(path, &body)).await;
+        (status, body)
+    }
+
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    fn generate_w_body() -> Value {
+        json!({
+            "key": "1234_5678,2345_6789",
+            "gt": "019924a82c70bb123aae90d483087f94",
+            "challenge": "6c3c2b5d1c5ab1e8e0e03a1ac2d4c5e8a1",
+            "c": [12, 58, 98],
+            "s": "2b5d1c5a",
+        })
+    }
+
+    #[tokio::test]
+    async fn legacy_routes_keep_their_json_shapes() {
+        let (status, body) = post_json("/click/generate_w", generate_w_body()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(keys(&body), ["data", "error", "request_id", "success"]);
+        assert!(body["data"].is_string());
+        assert!(body["request_id"].is_string());
+
+        let url = json!({ "url": "http://127.0.0.1:1/register" });
+        let (status, body) = post_json("/slide/register_test", url).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            keys(&body),
+            ["code", "data", "error", "request_id", "success"]
+        );
+        assert_eq!(body["code"], "EGRESS_BLOCKED");
+
+        let (status, _) = post_json("/slide/simple_match_retry", generate_w_body()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let registered = Output::Registered {
+            gt: "gt".to_string(),
+            challenge: "challenge".to_string(),
+        };
+        assert_eq!(
+            registered.render(Style::Legacy),
+            json!({ "first": "gt", "second": "challenge" })
+        );
+        assert_eq!(
+            Output::Type(VerifyType::Slide).render(Style::Legacy),
+            json!("slide")
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_routes_use_named_fields_and_error_codes() {
+        let (status, body) = post_json("/v1/click/generate_w", generate_w_body()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(keys(&body["data"]), ["w"]);
+
+        let (status, body) = post_json("/v1/slide/simple_match_retry", generate_w_body()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "UNSUPPORTED_OPERATION");
+        assert!(body["request_id"].is_string());
+
+        let (status, body) = post_json("/v1/click/verify", json!({ "gt": "only" })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "INVALID_REQUEST");
+
+        let registered = Output::Registered {
+            gt: "gt".to_string(),
+            challenge: "challenge".to_string(),
+        };
+        assert_eq!(
+            registered.render(Style::V1),
+            json!({ "gt": "gt", "challenge": "challenge" })
+        );
+        assert_eq!(
+            Output::Type(VerifyType::Click).render(Style::V1),
+            json!({ "captcha_type": "click" })
+        );
+    }
+
+    /// 类型只用点选实例检测一次，检测为滑块后交给滑块实例完成，并如实返回滑块类型
+    #[test]
+    fn auto_flow_hands_off_to_detected_type() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut flow = Solved(AutoFlow::Detect {
+            click: Scripted::<false>::new(&calls),
+            slide: Scripted::<true>::new(&calls),
+            gt: "gt".to_string(),
+            challenge: "challenge".to_string(),
+        });
+
+        assert!(matches!(flow.advance(), Ok(Step::Wait(_))));
+        let Ok(Step::Done(output)) = flow.advance() else {
+            panic!("提交后应得到识别结果");
+        };
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "click.get_c_s",
+                "click.get_type",
+                "slide.get_new_c_s_args",
+                "slide.verify"
+            ]
+        );
+        let rendered = output.render(Style::V1);
+        assert_eq!(rendered["captcha_type"], "slide");
+        assert_eq!(rendered["key"], "key-0");
+        assert_eq!(rendered["attempts"], 1);
+    }
+
+    /// 等待人工答复时不占用阻塞线程：阻塞线程池只有一个线程，流程入队后其他阻塞操作仍能执行
+    #[test]
+    fn parked_solve_does_not_hold_a_blocking_thread() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .max_blocking_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let manual = Arc::new(ManualRecognizer::new(Duration::from_secs(10)));
+        let state = AppState {
+            manual: Some(Arc::clone(&manual)),
+            ..AppState::new(None)
+        };
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        // 阻塞 Client 不能在异步上下文中释放，在运行时外保留一份
+        let solver = Scripted::<false>::new(&calls).with_manual(&manual);
+        runtime.block_on(async {
+            let solver = solver.clone();
+            let scope =
+                debug::RequestScope::new(false, "parked", "/click/simple_match", Value::Null);
+            let solve = tokio::spawn(drive(scope, move || {
+                Ok(Solved(SolveFlow::new(solver, "gt", "challenge")))
+            }));
+
+            let get = || {
+                axum::http::Request::get("/manual/tasks")
+                    .body(Body::empty())
+                    .unwrap()
+            };
+            let mut id = Value::Null;
+            for _ in 0..200 {
+                id = send_to(state.clone(), get()).await.2["data"][0]["id"].clone();
+                if !id.is_null() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert!(id.is_number(), "任务未入队");
+
+            let other = tokio::time::timeout(Duration::from_secs(5), blocking(|| Ok(1))).await;
+            assert!(matches!(other, Ok(Ok(1))), "阻塞线程被人工识别占用");
+
+            let answer = json!({ "points": [[12.5, 30.0]] });
+            let (status, _, _) = send_to(
+                state.clone(),
+                json_request(&format!("/manual/tasks/{id}"), &answer),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let Ok(Ok(Output::Solved(result))) = solve.await else {
+                panic!("答复后应完成识别");
+            };
+            assert_eq!(result.key, "[(12.5, 30.0)]");
+            assert_eq!(result.attempts, 1);
+        });
+    }
+
+    /// 调用方的 `X-Request-Id` 出现在响应头、响应体和调试产物目录名中，不合法或缺失时生成新的
+    #[tokio::test]
+    async fn request_id_is_reused_in_header_body_and_artifacts() {
+        let root = debug::artifacts_root_for_tests();
+        let with_id = |id: &str| {
+            let mut request = json_request("/v1/click/generate_w", &generate_w_body());
+            request
+                .headers_mut()
+                .insert(crate::REQUEST_ID_HEADER, id.parse().unwrap());
+            request
+        };
+        let debug_on = || AppState {
+            debug: true,
+            ..AppState::new(None)
+        };
+
+        let (status, headers, body) = send_to(debug_on(), with_id("abc-123")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[crate::REQUEST_ID_HEADER], "abc-123");
+        assert_eq!(body["request_id"], "abc-123");
+        let meta = std::fs::read(root.join("abc-123").join("meta.json")).unwrap();
+        let meta: Value = serde_json::from_slice(&meta).unwrap();
+        assert_eq!(meta["request_id"], "abc-123");
+        assert_eq!(meta["endpoint"], "/v1/click/generate_w");
+
+        let malformed = with_id("../abc 123");
+        let missing = json_request("/v1/click/generate_w", &generate_w_body());
+        for request in [malformed, missing] {
+            let (_, headers, body) = send_to(debug_on(), request).await;
+            let generated = headers[crate::REQUEST_ID_HEADER].to_str().unwrap();
+            assert!(debug::is_safe_id(generated), "{generated}");
+            assert_eq!(body["request_id"], generated);
+            assert!(root.join(generated).join("meta.json").is_file());
+            std::fs::remove_dir_all(root.join(generated)).unwrap();
+        }
+        std::fs::remove_dir_all(root.join("abc-123")).unwrap();
+
+        // 调试开关随 AppState 传入，关闭时同样回传请求 ID 但不写产物
+        let debug_off = AppState {
+            debug: false,
+            ..AppState::new(None)
+        };
+        let (_, headers, _) = send_to(debug_off, with_id("abc-off")).await;
+        assert_eq!(headers[crate::REQUEST_ID_HEADER], "abc-off");
+        assert!(!root.join("abc-off").exists());
+    }
+
+    /// 标注图接口只在调试模式下可用，返回已保存的 PNG，请求不存在或 ID 不安全时返回 404
+    #[tokio::test]
+    async fn annotated_route_serves_saved_png_only_in_debug_mode() {
+        let root = debug::artifacts_root_for_tests();
+        let id = "annotated-route-test";
+        std::fs::create_dir_all(root.join(id)).unwrap();
+        let path = root.join(id).join(format!("{}.png", debug::ANNOTATED));
+        image::DynamicImage::new_rgb8(4, 4).save(&path).unwrap();
+        let saved = std::fs::read(&path).unwrap();
+        let get = |uri: &str| axum::http::Request::get(uri).body(Body::empty()).unwrap();
+        let debug_on = || AppState {
+            debug: true,
+            ..AppState::new(None)
+        };
+
+        let uri = format!("/debug/artifacts/{id}/annotated");
+        let (status, headers, body) = call(debug_on(), get(&uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "image/png");
+        assert_eq!(body, saved);
+
+        for uri in [
+            "/debug/artifacts/missing-request/annotated",
+            "/debug/artifacts/..%2F..%2Fetc/annotated",
+        ] {
+            let (status, _, _) = call(debug_on(), get(uri)).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+
+        let debug_off = AppState {
+            debug: false,
+            ..AppState::new(None)
+        };
+        let (status, _, body) = call(debug_off, get(&uri)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "调试模式未开启");
+
+        std::fs::remove_dir_all(root.join(id)).unwrap();
+    }
+
+    /// 归档接口边打包边发送，大于管道缓冲区的产物也能完整下载
+    #[tokio::test]
+    async fn archive_route_streams_request_artifacts() {
+        let root = debug::artifacts_root_for_tests();
+        let id = "archive-route-test";
+        std::fs::create_dir_all(root.join(id)).unwrap();
+        let large = (0..300 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        std::fs::write(root.join(id).join("large.bin"), &large).unwrap();
+        let get = |uri: &str| axum::http::Request::get(uri).body(Body::empty()).unwrap();
+        let debug_on = || AppState {
+            debug: true,
+            ..AppState::new(None)
+        };
+
+        let uri = format!("/debug/artifacts/{id}/archive");
+        let (status, headers, body) = call(debug_on(), get(&uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/x-tar");
+        let mut archive = tar::Archive::new(body.as_slice());
+        let mut entry = archive
+            .entries()
+            .unwrap()
+            .map(Result::unwrap)
+            .find(|entry| entry.path().unwrap().ends_with("large.bin"))
+            .unwrap();
+        assert_eq!(
+            entry.path().unwrap(),
+            std::path::Path::new(id).join("large.bin")
+        );
+        let mut content = Vec::new();
+        std::io::Read::read_to_end(&mut entry, &mut content).unwrap();
+        assert_eq!(content, large);
+
+        let missing = call(debug_on(), get("/debug/artifacts/missing-request/archive")).await;
+        assert_eq!(missing.0, StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(root.join(id)).unwrap();
+    }
+
+    /// 未编译识别模型时识别接口直接返回 501，不请求上游；其余接口不受影响
+    #[cfg(not(feature = "recognition"))]
+    #[tokio::test]
+    async fn recognition_endpoints_report_missing_feature() {
+        let common = json!({ "gt": "gt", "challenge": "challenge" });
+        for path in ["/v1/click/simple_match", "/v1/auto/simple_match"] {
+            let (status, body) = post_json(path, common.clone()).await;
+            assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+            assert_eq!(body["code"], "RECOGNITION_NOT_COMPILED");
+        }
+        let (status, _) = post_json("/v1/click/generate_w", generate_w_body()).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
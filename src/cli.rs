@@ -0,0 +1,658 @@
+// cli.rs
+
+use crate::abstraction::{GenerateW, Test};
+use crate::click::Click;
+use crate::debug::{self, ArtifactConfig};
+use crate::egress::EgressPolicy;
+use crate::entropy::{Entropy, FixedClock, SeededRngSource};
+use crate::error::{other, other_without_source, Result};
+use crate::manual::DEFAULT_MANUAL_TIMEOUT;
+use crate::recognizer::{self, RecognizerConfig, RecognizerKind, DEFAULT_REMOTE_TIMEOUT};
+use crate::slide::Slide;
+use crate::telemetry::LogFormat;
+use crate::transport::{
+    LiveTransport, RecordingTransport, ReplayTransport, Transport, DEFAULT_IMAGE_HOST_TIMEOUT,
+    DEFAULT_MAX_IMAGE_BYTES,
+};
+use crate::ClientManager;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(version, about = "极验验证码识别服务与本地调试工具")]
+pub(crate) struct Cli {
+    /// 开启调试模式（也可设置环境变量 BILI_TICKET_GT_DEBUG=1）
+    #[arg(short, long, global = true)]
+    pub(crate) debug: bool,
+
+    /// 把上游请求和响应录制到该目录；`serve` 模式下每个请求各占一个子目录
+    #[arg(long, global = true, value_name = "DIR")]
+    pub(crate) record_dir: Option<PathBuf>,
+
+    /// 从该目录回放录制的上游响应，不访问网络（不适用于 `serve`）
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "record_dir")]
+    pub(crate) replay_dir: Option<PathBuf>,
+
+    /// 固定随机数种子，同样的输入和调用顺序生成同样的 w（不适用于 `serve`）
+    #[arg(long, global = true, value_name = "SEED")]
+    pub(crate) seed: Option<u64>,
+
+    /// 固定当前时间（Unix 毫秒时间戳），与 `--seed` 一起使用时 w 完全可复现（不适用于 `serve`）
+    #[arg(long, global = true, value_name = "MS")]
+    pub(crate) fixed_time_ms: Option<u64>,
+
+    /// 日志和错误中保留 w、validate、challenge 和识别结果的原文（默认只记录长度和摘要）；代理账号密码始终打码
+    #[arg(long, global = true)]
+    pub(crate) log_sensitive: bool,
+
+    #[command(flatten)]
+    pub(crate) artifacts: ArtifactArgs,
+
+    #[command(flatten)]
+    pub(crate) egress: EgressArgs,
+
+    #[command(flatten)]
+    pub(crate) recognizer: RecognizerArgs,
+
+    /// 验证码图片大小上限（KB），超过时视为上游异常
+    #[arg(long, global = true, value_name = "KB", default_value_t = DEFAULT_MAX_IMAGE_BYTES / 1024)]
+    pub(crate) max_image_size_kb: u64,
+
+    /// 单个图片服务器的下载超时秒数，超时后换上游下发的下一个图片服务器
+    #[arg(long, global = true, value_name = "SECS", default_value_t = DEFAULT_IMAGE_HOST_TIMEOUT.as_secs())]
+    pub(crate) image_host_timeout_secs: u64,
+
+    /// 日志输出格式
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "BILI_TICKET_GT_LOG_FORMAT",
+        default_value = "text"
+    )]
+    pub(crate) log_format: LogFormat,
+
+    /// OTLP/HTTP 采集器地址，例如 http://localhost:4318；不设置则不导出链路追踪
+    #[arg(
+        long,
+        global = true,
+        value_name = "URL",
+        env = "OTEL_EXPORTER_OTLP_ENDPOINT"
+    )]
+    pub(crate) otlp_endpoint: Option<String>,
+
+    /// 不指定子命令时等同于 `serve`
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+/// 调试产物的保存位置和保留策略，仅在调试模式下生效
+#[derive(Args)]
+pub(crate) struct ArtifactArgs {
+    /// 调试产物根目录，相对路径按启动目录解析
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        default_value = "debug_artifacts"
+    )]
+    artifacts_dir: PathBuf,
+
+    /// 调试产物最长保留小时数，0 表示不按时间清理
+    #[arg(long, global = true, value_name = "HOURS", default_value_t = 24)]
+    artifacts_max_age_hours: u64,
+
+    /// 调试产物总大小上限（MB），0 表示不限制
+    #[arg(long, global = true, value_name = "MB", default_value_t = 512)]
+    artifacts_max_size_mb: u64,
+}
+
+impl ArtifactArgs {
+    pub(crate) fn config(&self) -> ArtifactConfig {
+        ArtifactConfig {
+            root: self.artifacts_dir.clone(),
+            max_age: (self.artifacts_max_age_hours > 0)
+                .then(|| Duration::from_secs(self.artifacts_max_age_hours * 3600)),
+            max_bytes: (self.artifacts_max_size_mb > 0)
+                .then(|| self.artifacts_max_size_mb * 1024 * 1024),
+        }
+    }
+}
+
+/// 注册地址和图片地址的出站限制，防止借服务探测内网
+#[derive(Args)]
+pub(crate) struct EgressArgs {
+    /// 允许的协议，逗号分隔
+    #[arg(
+        long,
+        global = true,
+        value_name = "SCHEMES",
+        value_delimiter = ',',
+        default_value = "http,https"
+    )]
+    allowed_schemes: Vec<String>,
+
+    /// 注册地址的主机白名单，逗号分隔，支持 `*.example.com`；不指定则不限制主机
+    #[arg(long, global = true, value_name = "HOSTS", value_delimiter = ',')]
+    register_hosts: Vec<String>,
+
+    /// 验证码图片的主机白名单，逗号分隔，支持 `*.example.com`；不指定则不限制主机
+    #[arg(long, global = true, value_name = "HOSTS", value_delimiter = ',')]
+    image_hosts: Vec<String>,
+
+    /// 允许访问私有、回环和链路本地地址（默认拦截）
+    #[arg(long, global = true)]
+    allow_private_network: bool,
+}
+
+impl EgressArgs {
+    pub(crate) fn policy(&self) -> EgressPolicy {
+        EgressPolicy {
+            schemes: self.allowed_schemes.clone(),
+            register_hosts: self.register_hosts.clone(),
+            image_hosts: self.image_hosts.clone(),
+            allow_private: self.allow_private_network,
+        }
+    }
+}
+
+/// 点选和滑块使用的识别实现
+#[derive(Args)]
+pub(crate) struct RecognizerArgs {
+    /// 识别实现：local 为内置模型，remote 为把图片提交给 HTTP 识别服务，manual 为在 /manual 页面上人工识别（仅适用于 `serve`）
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "BILI_TICKET_GT_RECOGNIZER",
+        default_value = "local"
+    )]
+    recognizer: RecognizerKind,
+
+    /// 远程识别服务地址，`--recognizer remote` 时必填
+    #[arg(
+        long,
+        global = true,
+        value_name = "URL",
+        env = "BILI_TICKET_GT_RECOGNIZER_URL",
+        required_if_eq("recognizer", "remote")
+    )]
+    recognizer_url: Option<String>,
+
+    /// 远程识别服务的请求超时秒数
+    #[arg(long, global = true, value_name = "SECS", default_value_t = DEFAULT_REMOTE_TIMEOUT.as_secs())]
+    recognizer_timeout_secs: u64,
+
+    /// 人工识别的最长等待秒数，超时后本次识别失败
+    #[arg(long, global = true, value_name = "SECS", default_value_t = DEFAULT_MANUAL_TIMEOUT.as_secs())]
+    manual_timeout_secs: u64,
+}
+
+impl RecognizerArgs {
+    pub(crate) fn config(&self) -> RecognizerConfig {
+        RecognizerConfig {
+            kind: self.recognizer,
+            remote_url: self.recognizer_url.clone(),
+            remote_timeout: Duration::from_secs(self.recognizer_timeout_secs),
+            manual_timeout: Duration::from_secs(self.manual_timeout_secs),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// 启动 HTTP 服务
+    Serve(ServeArgs),
+    #[command(flatten)]
+    Tool(ToolCommand),
+}
+
+/// 本地调试用的子命令，执行一次后退出
+#[derive(Subcommand)]
+pub(crate) enum ToolCommand {
+    /// 识别本地点选验证码图片，输出 key
+    RecognizeClick {
+        /// 点选验证码图片路径
+        image: PathBuf,
+    },
+    /// 识别本地滑块验证码图片，输出滑动距离
+    RecognizeSlide {
+        /// 乱序背景图路径（即极验下发的 bg 图片）
+        bg: PathBuf,
+        /// 滑块图片路径
+        slice: PathBuf,
+    },
+    /// 根据 key 生成 w 参数
+    GenerateW(GenerateWArgs),
+    /// 从注册地址获取 gt/challenge 并完整走一遍识别和验证，输出 validate
+    Solve(SolveArgs),
+}
+
+const DEFAULT_BIND: &str = "0.0.0.0:3000";
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_UNIX_SOCKET_MODE: u32 = 0o660;
+
+impl ToolCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            ToolCommand::RecognizeClick { .. } => "recognize-click",
+            ToolCommand::RecognizeSlide { .. } => "recognize-slide",
+            ToolCommand::GenerateW(_) => "generate-w",
+            ToolCommand::Solve(_) => "solve",
+        }
+    }
+}
+
+#[derive(Args)]
+pub(crate) struct ServeArgs {
+    /// 明文 HTTP 监听地址，可重复指定；未指定任何监听器时为 0.0.0.0:3000
+    #[arg(long, value_name = "ADDR")]
+    pub(crate) bind: Vec<String>,
+
+    /// HTTPS 监听地址，可重复指定，需要同时指定 --tls-cert 和 --tls-key
+    #[arg(long, value_name = "ADDR", requires_all = ["tls_cert", "tls_key"])]
+    pub(crate) tls_bind: Vec<String>,
+
+    /// PEM 格式的证书链文件，文件内容变化后自动重新加载
+    #[arg(long, value_name = "FILE")]
+    pub(crate) tls_cert: Option<PathBuf>,
+
+    /// PEM 格式的私钥文件
+    #[arg(long, value_name = "FILE")]
+    pub(crate) tls_key: Option<PathBuf>,
+
+    /// Unix 域套接字路径，可重复指定
+    #[arg(long, value_name = "PATH")]
+    pub(crate) unix_socket: Vec<PathBuf>,
+
+    /// Unix 域套接字文件权限（八进制）
+    #[arg(long, value_name = "MODE", default_value = "660", value_parser = parse_mode)]
+    pub(crate) unix_socket_mode: u32,
+
+    /// 收到 SIGTERM/SIGINT 后等待处理中请求完成的最长秒数
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_DRAIN_TIMEOUT_SECS)]
+    pub(crate) drain_timeout_secs: u64,
+}
+
+impl Default for ServeArgs {
+    fn default() -> Self {
+        Self {
+            bind: Vec::new(),
+            tls_bind: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            unix_socket: Vec::new(),
+            unix_socket_mode: DEFAULT_UNIX_SOCKET_MODE,
+            drain_timeout_secs: DEFAULT_DRAIN_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl ServeArgs {
+    /// 明文 HTTP 监听地址；一个监听器都没有指定时使用默认地址
+    pub(crate) fn plain_binds(&self) -> Vec<&str> {
+        if self.bind.is_empty() && self.tls_bind.is_empty() && self.unix_socket.is_empty() {
+            vec![DEFAULT_BIND]
+        } else {
+            self.bind.iter().map(String::as_str).collect()
+        }
+    }
+}
+
+fn parse_mode(value: &str) -> std::result::Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("无效的八进制权限: {value}"))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum CaptchaKind {
+    Click,
+    Slide,
+}
+
+#[derive(Args)]
+pub(crate) struct GenerateWArgs {
+    /// 验证码类型
+    kind: CaptchaKind,
+    /// 点选为坐标 key，滑块为滑动距离
+    #[arg(long)]
+    key: String,
+    #[arg(long)]
+    gt: String,
+    #[arg(long)]
+    challenge: String,
+    /// 逗号分隔的 c 参数，例如 12,58,98,36,43,95,62,15,12
+    #[arg(long, value_delimiter = ',')]
+    c: Vec<u8>,
+    #[arg(long)]
+    s: String,
+}
+
+#[derive(Args)]
+pub(crate) struct SolveArgs {
+    /// 申请验证码的注册地址
+    #[arg(long)]
+    register_url: String,
+    /// 验证码类型
+    #[arg(long = "type", value_enum, default_value = "click")]
+    kind: CaptchaKind,
+    /// 请求极验接口使用的代理
+    #[arg(long)]
+    proxy: Option<String>,
+}
+
+/// ### 执行除 `serve` 以外的子命令
+/// - 结果输出到标准输出，错误由调用方打印
+pub(crate) fn run(command: ToolCommand, cli: &Cli) -> Result<()> {
+    let upstream = Upstream {
+        transport: transport(cli)?,
+        entropy: entropy(cli),
+    };
+    let endpoint = format!("cli/{}", command.name());
+    let request_id = debug::new_request_id();
+    let output = debug::with_request(
+        debug::enabled(),
+        &request_id,
+        &endpoint,
+        Value::Null,
+        || execute(command, upstream),
+    )?;
+    println!("{output}");
+    Ok(())
+}
+
+fn execute(command: ToolCommand, upstream: Upstream) -> Result<String> {
+    let output = match command {
+        ToolCommand::RecognizeClick { image } => {
+            ensure_recognizer()?;
+            let pic_img = load_image(&image)?;
+            new_click(None, upstream)?.recognize(pic_img)?.wait()?
+        }
+        ToolCommand::RecognizeSlide { bg, slice } => {
+            ensure_recognizer()?;
+            let bg_img = load_image(&bg)?;
+            let slice_img = load_image(&slice)?;
+            new_slide(None, upstream)?
+                .recognize(&bg_img, slice_img)?
+                .wait()?
+        }
+        ToolCommand::GenerateW(args) => match args.kind {
+            CaptchaKind::Click => new_click(None, upstream)?.generate_w(
+                &args.key,
+                &args.gt,
+                &args.challenge,
+                &args.c,
+                &args.s,
+            )?,
+            CaptchaKind::Slide => new_slide(None, upstream)?.generate_w(
+                &args.key,
+                &args.gt,
+                &args.challenge,
+                &args.c,
+                &args.s,
+            )?,
+        },
+        ToolCommand::Solve(args) => {
+            ensure_recognizer()?;
+            match args.kind {
+                CaptchaKind::Click => {
+                    new_click(args.proxy.as_deref(), upstream)?.test(&args.register_url)?
+                }
+                CaptchaKind::Slide => {
+                    new_slide(args.proxy.as_deref(), upstream)?.test(&args.register_url)?
+                }
+            }
+        }
+    };
+    Ok(output)
+}
+
+/// ### 识别之前检查识别实现
+/// - 人工识别只能在 `serve` 的 /manual 页面上提交，命令行中无人应答
+/// - 生成 w 等不识别的子命令不受影响
+fn ensure_recognizer() -> Result<()> {
+    if recognizer::manual().is_some() {
+        return Err(other_without_source(
+            "人工识别需要通过 /manual 页面提交，仅适用于 serve",
+        ));
+    }
+    recognizer::ensure_available()
+}
+
+fn transport(cli: &Cli) -> Result<Arc<dyn Transport>> {
+    if let Some(dir) = &cli.replay_dir {
+        return Ok(Arc::new(ReplayTransport::load(dir)?));
+    }
+    if let Some(dir) = &cli.record_dir {
+        return Ok(Arc::new(RecordingTransport::new(
+            Arc::new(LiveTransport),
+            dir.clone(),
+        )));
+    }
+    Ok(Arc::new(LiveTransport))
+}
+
+/// 命令行实例共用的上游请求方式和随机数与时钟
+struct Upstream {
+    transport: Arc<dyn Transport>,
+    entropy: Entropy,
+}
+
+fn entropy(cli: &Cli) -> Entropy {
+    let mut entropy = Entropy::default();
+    if let Some(seed) = cli.seed {
+        entropy.rng = Arc::new(SeededRngSource::new(seed));
+    }
+    if let Some(now_ms) = cli.fixed_time_ms {
+        entropy.clock = Arc::new(FixedClock(now_ms));
+    }
+    entropy
+}
+
+fn load_image(path: &Path) -> Result<image::DynamicImage> {
+    image::open(path).map_err(|e| other(&format!("图片加载失败: {}", path.display()), e))
+}
+
+fn new_click(proxy: Option<&str>, upstream: Upstream) -> Result<Click> {
+    let manager = ClientManager::new();
+    let client = manager.get(proxy, None, None)?;
+    let download_client = manager.get(None, None, None)?;
+    let mut click = Click::new(client, download_client);
+    click.set_transport(upstream.transport);
+    click.set_entropy(upstream.entropy);
+    Ok(click)
+}
+
+fn new_slide(proxy: Option<&str>, upstream: Upstream) -> Result<Slide> {
+    let manager = ClientManager::new();
+    let client = manager.get(proxy, None, None)?;
+    let download_client = manager.get(None, None, None)?;
+    let mut slide = Slide::new(client, download_client);
+    slide.set_transport(upstream.transport);
+    slide.set_entropy(upstream.entropy);
+    Ok(slide)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RECOGNITION_NOT_COMPILED;
+    use crate::telemetry::LogFormat;
+    use std::fs;
+
+    fn parse(args: &[&str]) -> std::result::Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("bili_ticket_gt_server").chain(args.iter().copied()))
+    }
+
+    fn tool(cli: &mut Cli) -> ToolCommand {
+        match cli.command.take() {
+            Some(Command::Tool(command)) => command,
+            _ => panic!("应解析为本地调试子命令"),
+        }
+    }
+
+    #[test]
+    fn parses_global_flags_subcommands_and_env() {
+        let cli = parse(&[]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.max_image_size_kb, DEFAULT_MAX_IMAGE_BYTES / 1024);
+
+        // 全局参数可以写在子命令之后
+        let mut cli = parse(&[
+            "generate-w",
+            "slide",
+            "--key",
+            "87",
+            "--gt",
+            "g",
+            "--challenge",
+            "c",
+            "--c",
+            "12,58,98",
+            "--s",
+            "s",
+            "--seed",
+            "7",
+            "--register-hosts",
+            "*.bilibili.com,api.geetest.com",
+        ])
+        .unwrap();
+        assert_eq!(cli.seed, Some(7));
+        assert_eq!(
+            cli.egress.policy().register_hosts,
+            ["*.bilibili.com", "api.geetest.com"]
+        );
+        let ToolCommand::GenerateW(args) = tool(&mut cli) else {
+            panic!("应解析为 generate-w");
+        };
+        assert!(matches!(args.kind, CaptchaKind::Slide));
+        assert_eq!(args.c, [12, 58, 98]);
+
+        let cli = parse(&[
+            "serve",
+            "--bind",
+            "127.0.0.1:0",
+            "--unix-socket-mode",
+            "600",
+        ])
+        .unwrap();
+        let Some(Command::Serve(args)) = cli.command else {
+            panic!("应解析为 serve");
+        };
+        assert_eq!(args.plain_binds(), ["127.0.0.1:0"]);
+        assert_eq!(args.unix_socket_mode, 0o600);
+
+        assert!(parse(&["serve", "--unix-socket-mode", "999"]).is_err());
+        assert!(parse(&[
+            "--record-dir",
+            "a",
+            "--replay-dir",
+            "b",
+            "recognize-click",
+            "a.png"
+        ])
+        .is_err());
+        assert!(parse(&["--recognizer", "remote", "recognize-click", "a.png"]).is_err());
+
+        // 环境变量只在这一个测试中设置，命令行参数优先于环境变量
+        std::env::set_var("BILI_TICKET_GT_RECOGNIZER", "remote");
+        std::env::set_var("BILI_TICKET_GT_RECOGNIZER_URL", "http://127.0.0.1:9000");
+        std::env::set_var("BILI_TICKET_GT_LOG_FORMAT", "json");
+        let from_env = parse(&["recognize-click", "a.png"]);
+        let overridden = parse(&["--recognizer", "local", "recognize-click", "a.png"]);
+        std::env::remove_var("BILI_TICKET_GT_RECOGNIZER");
+        std::env::remove_var("BILI_TICKET_GT_RECOGNIZER_URL");
+        std::env::remove_var("BILI_TICKET_GT_LOG_FORMAT");
+
+        let from_env = from_env.unwrap();
+        let config = from_env.recognizer.config();
+        assert_eq!(config.kind, RecognizerKind::Remote);
+        assert_eq!(config.remote_url.as_deref(), Some("http://127.0.0.1:9000"));
+        assert!(matches!(from_env.log_format, LogFormat::Json));
+        assert_eq!(
+            overridden.unwrap().recognizer.config().kind,
+            RecognizerKind::Local
+        );
+    }
+
+    #[test]
+    fn execute_runs_offline_against_a_replay_dir() {
+        let dir = std::env::temp_dir().join(format!("gt-cli-replay-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_arg = dir.to_str().unwrap();
+
+        // 回放目录为空，生成 w 时一旦访问上游就会失败；固定种子和时间后结果可复现
+        let generate = || {
+            let mut cli = parse(&[
+                "--replay-dir",
+                dir_arg,
+                "--seed",
+                "42",
+                "--fixed-time-ms",
+                "1700000000000",
+                "generate-w",
+                "click",
+                "--key",
+                "1234_5678,2345_6789",
+                "--gt",
+                "019924a82c70bb123aae90d483087f94",
+                "--challenge",
+                "6c3c2b5d1c5ab1e8e0e03a1ac2d4c5e8a1",
+                "--c",
+                "12,58,98,36,43,95,62,15,12",
+                "--s",
+                "5a2c3d4e",
+            ])
+            .unwrap();
+            let upstream = Upstream {
+                transport: transport(&cli).unwrap(),
+                entropy: entropy(&cli),
+            };
+            execute(tool(&mut cli), upstream).unwrap()
+        };
+        let w = generate();
+        assert!(!w.is_empty());
+        assert_eq!(w, generate());
+
+        // 注册地址的响应从回放目录读取
+        let register_url = "https://passport.bilibili.com/x/passport-login/captcha";
+        let exchange = serde_json::json!({
+            "url": register_url,
+            "query": [],
+            "status": 503,
+            "content_type": "text/html",
+            "body_file": "000.txt",
+            "error": null,
+        });
+        fs::write(dir.join("000.json"), exchange.to_string()).unwrap();
+        fs::write(dir.join("000.txt"), "<html>503</html>").unwrap();
+        let mut cli = parse(&[
+            "--replay-dir",
+            dir_arg,
+            "solve",
+            "--type",
+            "slide",
+            "--register-url",
+            register_url,
+        ])
+        .unwrap();
+        let upstream = Upstream {
+            transport: transport(&cli).unwrap(),
+            entropy: entropy(&cli),
+        };
+        let err = execute(tool(&mut cli), upstream).unwrap_err();
+        if cfg!(feature = "recognition") {
+            assert_eq!(err.upstream().and_then(|failure| failure.status), Some(503));
+        } else {
+            assert_eq!(err.code(), Some(RECOGNITION_NOT_COMPILED));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
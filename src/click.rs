@@ -1,36 +1,31 @@
 // click.rs
 
-use crate::abstraction::{Api, GenerateW, Test, VerifyType};
+use crate::abstraction::{Api, GenerateW, ImageUrl, Test, VerifyOutcome, VerifyType};
+use crate::annotate;
 use crate::debug;
-use crate::error::{
-    missing_param, net_work_error, other, other_without_source, parse_error, Result,
-};
+use crate::entropy::Entropy;
+use crate::egress::{self, Target};
+use crate::error::{missing_param, other, parse_error, Result};
+use crate::manual::Answer;
+use crate::recognizer::{self, ClickRecognizer};
+use crate::redact;
+use crate::solve::{self, Keyed, Solve, SolveFlow};
+use crate::transport::{fetch, Expected, LiveTransport, Transport};
 use crate::w::click_calculate;
-use captcha_breaker::captcha::ChineseClick0;
-use captcha_breaker::environment::CaptchaEnvironment;
-use once_cell::sync::Lazy;
+use image::DynamicImage;
 use reqwest::blocking::Client;
 use serde_json::Value;
-use std::collections::HashMap;
 use std::sync::Arc;
-use std::thread::sleep;
-// 修改：引入 SystemTime 和 UNIX_EPOCH 用于生成时间戳
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-
-static GLOBAL_CLICK_BREAKER: Lazy<Arc<ChineseClick0>> = Lazy::new(|| {
-    println!("Loading ChineseClick0 ONNX model... This should only happen once.");
-    let env = CaptchaEnvironment::default();
-    let breaker = env.load_captcha_breaker::<ChineseClick0>().unwrap();
-    println!("Model loaded successfully.");
-    Arc::new(breaker)
-});
+use std::time::Instant;
 
 #[derive(Clone)]
 pub struct Click {
     client: Arc<Client>,
     download_client: Arc<Client>,
+    transport: Arc<dyn Transport>,
+    entropy: Entropy,
     verify_type: VerifyType,
-    cb: Arc<ChineseClick0>,
+    recognizer: Arc<dyn ClickRecognizer>,
 }
 
 impl Click {
@@ -38,8 +33,10 @@ impl Click {
         Click {
             client,
             download_client,
+            transport: Arc::new(LiveTransport),
+            entropy: Entropy::default(),
             verify_type: VerifyType::Click,
-            cb: Arc::clone(&GLOBAL_CLICK_BREAKER),
+            recognizer: recognizer::click(),
         }
     }
 
@@ -48,71 +45,73 @@ impl Click {
         self.download_client = new_download_client;
     }
 
-    pub fn simple_match(&mut self, gt: &str, challenge: &str) -> Result<String> {
-        self.get_c_s(gt, challenge, None)?;
-        self.get_type(gt, challenge, None)?;
-        let (c, s, args) = self.get_new_c_s_args(gt, challenge)?;
-        let start = Instant::now();
-        let key = self.calculate_key(args)?;
-        let w = self.generate_w(key.as_str(), gt, challenge, c.as_ref(), s.as_str())?;
-
-        let elapsed = start.elapsed();
-        if elapsed < Duration::from_secs(2) {
-            let sleep_duration = Duration::from_secs(2) - elapsed;
-            sleep(sleep_duration);
-        }
-        let (_, validate) = self.verify(gt, challenge, Some(w.as_str()))?;
-        Ok(validate)
+    /// 替换上游请求的发送方式，用于录制或回放
+    pub(crate) fn set_transport(&mut self, transport: Arc<dyn Transport>) {
+        self.transport = transport;
     }
 
-    pub fn simple_match_retry(&mut self, gt: &str, challenge: &str) -> Result<String> {
-        self.get_c_s(gt, challenge, None)?;
-        self.get_type(gt, challenge, None)?;
-        let (c, s, mut args) = self.get_new_c_s_args(gt, challenge)?;
-        let mut last_error = None;
-
-        for attempt in 0..5 {
-            match self.vvv(gt, challenge, &c, s.as_str(), args) {
-                Ok(result) => return Ok(result),
-                Err(err) => last_error = Some(err),
-            }
-
-            if attempt == 4 {
-                break;
-            }
-
-            sleep(Duration::from_millis(250));
-            args = self.refresh(gt, challenge)?;
-        }
-
-        Err(last_error.unwrap_or_else(|| other_without_source("多次重试后仍未通过验证")))
+    /// 替换随机数与时钟，固定后生成的 w 可以复现
+    pub(crate) fn set_entropy(&mut self, entropy: Entropy) {
+        self.entropy = entropy;
     }
 
-    fn vvv(
-        &mut self,
-        gt: &str,
-        challenge: &str,
-        c: &Vec<u8>,
-        s: &str,
-        args: String,
-    ) -> Result<String> {
-        let start = Instant::now();
-        let key = self.calculate_key(args)?;
-        let w = self.generate_w(key.as_str(), gt, challenge, c.as_ref(), s)?;
-
-        let elapsed = start.elapsed();
-        if elapsed < Duration::from_secs(2) {
-            let sleep_duration = Duration::from_secs(2) - elapsed;
-            sleep(sleep_duration);
+    /// ### 识别点选图片
+    /// - 命令行和 HTTP 服务共用此逻辑，返回可直接用于生成 w 的 key
+    /// - 人工识别时返回入队的任务，收到答复后再算出 key
+    pub(crate) fn recognize(&self, pic_img: DynamicImage) -> Result<Keyed> {
+        let _span = tracing::info_span!("inference").entered();
+        let inference_started_at = Instant::now();
+        if let Some(parked) = self.recognizer.park(&pic_img)? {
+            let finish = move |answer: Answer| {
+                Ok(click_key(&pic_img, &answer.points()?, inference_started_at))
+            };
+            return Ok(Keyed::Parked(parked, Box::new(finish)));
         }
+        let cb_res = self.recognizer.recognize(&pic_img).inspect_err(|e| {
+            tracing::debug!(
+                error = %e,
+                inference_ms = inference_started_at.elapsed().as_millis(),
+                "点选识别执行失败"
+            )
+        })?;
+        Ok(Keyed::Ready(click_key(
+            &pic_img,
+            &cb_res,
+            inference_started_at,
+        )))
+    }
+}
 
-        let (_, validate) = self.verify(gt, challenge, Some(w.as_str()))?;
-        Ok(validate)
+/// 把识别出的坐标换算为 key，并记录调试产物
+fn click_key(
+    pic_img: &DynamicImage,
+    cb_res: &[(f32, f32)],
+    inference_started_at: Instant,
+) -> String {
+    let mut res = vec![];
+    for (x, y) in cb_res {
+        let position = format!(
+            "{}_{}",
+            (x / 333.375 * 100f32 * 100f32).round(),
+            (y / 333.375 * 100f32 * 100f32).round()
+        );
+        res.push(position);
     }
+    let key = res.join(",");
+    tracing::debug!(
+        point_count = cb_res.len(),
+        key = %redact::secret(&key),
+        inference_ms = inference_started_at.elapsed().as_millis(),
+        "点选识别完成"
+    );
+    debug::save_rendered(debug::ANNOTATED, || annotate::click(pic_img, cb_res));
+    debug::record_timing("inference", inference_started_at.elapsed());
+    debug::record_key(&key);
+    key
 }
 
 impl Api for Click {
-    type ArgsType = String;
+    type ArgsType = ImageUrl;
 
     fn client(&self) -> &Client {
         &self.client
@@ -120,10 +119,18 @@ impl Api for Click {
     fn download_client(&self) -> &Client {
         &self.download_client
     }
+    fn transport(&self) -> &dyn Transport {
+        self.transport.as_ref()
+    }
+    fn entropy(&self) -> &Entropy {
+        &self.entropy
+    }
 
     fn register_test(&self, url: &str) -> crate::error::Result<(String, String)> {
-        let res = self.client().get(url).send().map_err(net_work_error)?;
-        let res = res.json::<Value>().map_err(parse_error)?;
+        let _span = tracing::info_span!("register").entered();
+        egress::check(url, Target::Register)?;
+        let res = fetch(self.transport(), self.client(), url, &[], Expected::Json)?;
+        let res: Value = serde_json::from_slice(&res.body).map_err(parse_error)?;
         let res_data = res
             .get("data")
             .ok_or_else(|| missing_param("data"))?
@@ -150,23 +157,21 @@ impl Api for Click {
         gt: &str,
         challenge: &str,
     ) -> Result<(Vec<u8>, String, Self::ArgsType)> {
-        // 修改：生成动态回调
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis()
-            .to_string();
-        let callback = format!("geetest_{}", timestamp);
-
+        let _span = tracing::info_span!("get_new_c_s_args").entered();
         let url = "https://api.geetest.com/get.php";
-        let mut params = HashMap::from([
+        let params = [
             ("gt", gt),
             ("challenge", challenge),
             ("is_next", "true"),
             ("offline", "false"),
             ("isPC", "true"),
-            ("callback", callback.as_str()), // 使用动态回调
-            ("type", "click"),
+            (
+                "type",
+                match self.verify_type {
+                    VerifyType::Click => "click",
+                    VerifyType::Slide => "slide",
+                },
+            ),
             ("lang", "zh-cn"),
             ("https", "false"),
             ("protocol", "https://"),
@@ -174,45 +179,12 @@ impl Api for Click {
             ("api_server", "api.geetest.com"),
             ("autoReset", "true"),
             ("width", "100%"),
-        ]);
-
-        params.insert(
-            "type",
-            match self.verify_type {
-                VerifyType::Click => "click",
-                VerifyType::Slide => "slide",
-            },
-        );
-        let res = self
-            .client
-            .get(url)
-            .query(&params)
-            .send()
-            .map_err(net_work_error)?;
-        let res = res.text().map_err(|e| other("什么b玩意错误", e))?;
-
-        // 修改：使用动态回调作为前缀
-        let prefix = format!("{}(", callback);
-        let res = res
-            .strip_prefix(&prefix)
-            .ok_or_else(|| other_without_source("前缀错误"))?
-            .strip_suffix(")")
-            .ok_or_else(|| other_without_source("后缀错误"))?;
-
-        let res: Value = serde_json::from_str(res).map_err(parse_error)?;
+        ];
+        let res = self.jsonp(url, &params)?;
         let res_data = res.get("data").ok_or_else(|| missing_param("data"))?;
         let c: Vec<u8> =
             serde_json::from_value(res_data.get("c").ok_or_else(|| missing_param("c"))?.clone())
                 .map_err(parse_error)?;
-        let static_server = res_data
-            .get("static_servers")
-            .ok_or_else(|| missing_param("static_servers"))?
-            .as_array()
-            .ok_or_else(|| missing_param("static_servers"))?
-            .first()
-            .ok_or_else(|| other_without_source("static_servers里面咋没东西啊"))?
-            .as_str()
-            .ok_or_else(|| other_without_source("static_servers里面咋没东西啊"))?;
         Ok((
             c,
             res_data
@@ -221,176 +193,39 @@ impl Api for Click {
                 .as_str()
                 .ok_or_else(|| missing_param("s"))?
                 .to_string(),
-            format!(
-                "https://{}{}",
-                static_server,
-                res_data
-                    .get("pic")
-                    .ok_or_else(|| missing_param("pic"))?
-                    .as_str()
-                    .ok_or_else(|| missing_param("pic"))?
-                    .strip_prefix("/")
-                    .ok_or_else(|| other_without_source("我真不想编错误名了"))?
-            ),
+            ImageUrl::from_response(res_data, "static_servers", "pic")?,
         ))
     }
 
-    fn verify(&self, gt: &str, challenge: &str, w: Option<&str>) -> Result<(String, String)> {
-        // 修改：生成动态回调
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis()
-            .to_string();
-        let callback = format!("geetest_{}", timestamp);
-
+    fn verify(&self, gt: &str, challenge: &str, w: Option<&str>) -> Result<VerifyOutcome> {
+        let _span = tracing::info_span!("verify").entered();
         let url = "https://api.geetest.com/ajax.php";
-        let mut params = HashMap::from([
+        let mut params = vec![
             ("gt", gt),
             ("challenge", challenge),
             ("lang", "zh-cn"),
             ("pt", "0"),
             ("client_type", "web"),
-            ("callback", callback.as_str()), // 使用动态回调
-        ]);
+        ];
         if let Some(w) = w {
-            params.insert("w", w);
+            params.push(("w", w));
         }
-        let res = self
-            .client
-            .get(url)
-            .query(&params)
-            .send()
-            .map_err(net_work_error)?;
-        let res = res.text().map_err(|e| other("什么b玩意错误", e))?;
-
-        // 修改：使用动态回调作为前缀
-        let prefix = format!("{}(", callback);
-        let res = res
-            .strip_prefix(&prefix)
-            .ok_or_else(|| other_without_source("前缀错误"))?
-            .strip_suffix(")")
-            .ok_or_else(|| other_without_source("后缀错误"))?;
-
-        let res: Value = serde_json::from_str(res).map_err(parse_error)?;
-        let res_data = res.get("data").ok_or_else(|| missing_param("data"))?;
-        Ok((
-            res_data
-                .get("result")
-                .ok_or_else(|| missing_param("result"))?
-                .as_str()
-                .ok_or_else(|| missing_param("result"))?
-                .to_string(),
-            res_data
-                .get("validate")
-                .ok_or_else(|| missing_param("validate"))?
-                .as_str()
-                .ok_or_else(|| missing_param("validate"))?
-                .to_string(),
-        ))
+        let res = self.jsonp(url, &params)?;
+        Ok(VerifyOutcome::from_response(&res))
     }
 
     fn refresh(&self, gt: &str, challenge: &str) -> Result<Self::ArgsType> {
-        // 修改：生成动态回调
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis()
-            .to_string();
-        let callback = format!("geetest_{}", timestamp);
-
+        let _span = tracing::info_span!("refresh").entered();
         let url = "https://api.geetest.com/refresh.php";
-        let params = HashMap::from([
-            ("gt", gt),
-            ("challenge", challenge),
-            ("callback", callback.as_str()), // 使用动态回调
-        ]);
-        let res = self
-            .client
-            .get(url)
-            .query(&params)
-            .send()
-            .map_err(net_work_error)?;
-        let res = res.text().map_err(|e| other("什么b玩意错误", e))?;
-
-        // 修改：使用动态回调作为前缀
-        let prefix = format!("{}(", callback);
-        let res = res
-            .strip_prefix(&prefix)
-            .ok_or_else(|| other_without_source("前缀错误"))?
-            .strip_suffix(")")
-            .ok_or_else(|| other_without_source("后缀错误"))?;
-
-        let res: Value = serde_json::from_str(res).map_err(parse_error)?;
+        let res = self.jsonp(url, &[("gt", gt), ("challenge", challenge)])?;
         let res_data = res.get("data").ok_or_else(|| missing_param("data"))?;
-        let static_server = res_data
-            .get("image_servers")
-            .ok_or_else(|| missing_param("image_servers"))?
-            .as_array()
-            .ok_or_else(|| missing_param("image_servers"))?
-            .first()
-            .ok_or_else(|| other_without_source("image_servers里面咋没东西啊"))?
-            .as_str()
-            .ok_or_else(|| other_without_source("image_servers里面咋没东西啊"))?;
-        Ok(format!(
-            "https://{}{}",
-            static_server,
-            res_data
-                .get("pic")
-                .ok_or_else(|| missing_param("pic"))?
-                .as_str()
-                .ok_or_else(|| missing_param("pic"))?
-                .strip_prefix("/")
-                .ok_or_else(|| other_without_source("我真不想编错误名了"))?
-        ))
+        ImageUrl::from_response(res_data, "image_servers", "pic")
     }
 }
 
 impl GenerateW for Click {
     fn calculate_key(&mut self, args: Self::ArgsType) -> Result<String> {
-        let started_at = Instant::now();
-        let pic_url = args;
-        tracing::debug!(url_length = pic_url.len(), "开始下载点选验证码图片");
-        let pic_bytes = self.download_img(pic_url.as_str())?;
-        let pic_img = image::load_from_memory(&pic_bytes).map_err(|e| other("图片加载失败", e))?;
-        tracing::debug!(
-            bytes = pic_bytes.len(),
-            width = pic_img.width(),
-            height = pic_img.height(),
-            "点选验证码图片已加载"
-        );
-        debug::save_image("click", &pic_img);
-
-        let inference_started_at = Instant::now();
-        let cb_res = self
-            .cb
-            .run(&pic_img)
-            .map_err(|e| {
-                tracing::debug!(
-                    error = %e,
-                    inference_ms = inference_started_at.elapsed().as_millis(),
-                    "点选识别模型执行失败"
-                );
-                other_without_source(&format!("cb模块内部错误: {}", e))
-            })?;
-        let mut res = vec![];
-        for (x, y) in &cb_res {
-            let position = format!(
-                "{}_{}",
-                (x / 333.375 * 100f32 * 100f32).round(),
-                (y / 333.375 * 100f32 * 100f32).round()
-            );
-            res.push(position);
-        }
-        let key = res.join(",");
-        tracing::debug!(
-            point_count = cb_res.len(),
-            key = %key,
-            inference_ms = inference_started_at.elapsed().as_millis(),
-            total_ms = started_at.elapsed().as_millis(),
-            "点选识别完成"
-        );
-        Ok(key)
+        self.begin_key(args)?.wait()
     }
 
     fn generate_w(
@@ -401,7 +236,8 @@ impl GenerateW for Click {
         _c: &[u8],
         _s: &str,
     ) -> Result<String> {
-        let w = click_calculate(key, gt, challenge)?;
+        let _span = tracing::info_span!("generate_w").entered();
+        let w = click_calculate(key, gt, challenge, &self.entropy)?;
         tracing::debug!(
             point_count = key.split(',').filter(|point| !point.is_empty()).count(),
             key_length = key.len(),
@@ -414,21 +250,39 @@ impl GenerateW for Click {
 
 impl Test for Click {
     fn test(&mut self, url: &str) -> Result<String> {
-        let (gt, challenge) = self.register_test(url)?;
-        let (c, s) = self.get_c_s(gt.as_str(), challenge.as_str(), None)?;
-        self.get_type(gt.as_str(), challenge.as_str(), None)?;
-        let (_c, _s, args) = self.get_new_c_s_args(gt.as_str(), challenge.as_str())?;
-        let key = self.calculate_key(args)?;
-        let w = self.generate_w(
-            key.as_str(),
-            gt.as_str(),
-            challenge.as_str(),
-            c.as_ref(),
-            s.as_str(),
-        )?;
+        let result = solve::run_to_end(SolveFlow::register(self.clone(), url))?;
+        Ok(result.validate)
+    }
+}
 
-        sleep(Duration::new(2, 0));
-        let (_, validate) = self.verify(gt.as_str(), challenge.as_str(), Some(w.as_str()))?;
-        Ok(validate)
+impl Solve for Click {
+    const TYPE: VerifyType = VerifyType::Click;
+
+    fn begin_key(&mut self, args: Self::ArgsType) -> Result<Keyed> {
+        let started_at = Instant::now();
+        let pic = args;
+        tracing::debug!(
+            server_count = pic.servers.len(),
+            path_length = pic.path.len(),
+            "开始下载点选验证码图片"
+        );
+        let pic_bytes = self.download_img(&pic)?;
+        debug::record_timing("download", started_at.elapsed());
+        let pic_img = image::load_from_memory(&pic_bytes).map_err(|e| other("图片加载失败", e))?;
+        tracing::debug!(
+            bytes = pic_bytes.len(),
+            width = pic_img.width(),
+            height = pic_img.height(),
+            "点选验证码图片已加载"
+        );
+        debug::save_image("click", &pic_img);
+        let keyed = self.recognize(pic_img)?;
+        if let Keyed::Ready(_) = keyed {
+            tracing::debug!(
+                total_ms = started_at.elapsed().as_millis(),
+                "点选 key 计算完成"
+            );
+        }
+        Ok(keyed)
     }
 }
@@ -1,21 +1,44 @@
+use crate::error::{Error, Result};
 use image::{DynamicImage, ImageFormat};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::OnceLock;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 static DEBUG_ENABLED: OnceLock<bool> = OnceLock::new();
+static ARTIFACT_CONFIG: OnceLock<ArtifactConfig> = OnceLock::new();
 static ARTIFACT_COUNTER: AtomicU64 = AtomicU64::new(0);
 
-pub(crate) fn init_from_startup_options() -> bool {
-    let enabled = env::args().any(|arg| arg == "--debug" || arg == "-d")
+thread_local! {
+    /// 当前线程正在处理的请求；分段执行的请求每段可能在不同线程上，由 `RequestScope::enter` 放入和取回
+    static CURRENT_REQUEST: RefCell<Option<RequestArtifacts>> = const { RefCell::new(None) };
+}
+
+/// ### 调试产物配置
+/// - root: 产物根目录，每个请求一个子目录
+/// - max_age: 超过该时长的产物会被清理
+/// - max_bytes: 产物总大小超过该值时从最旧的开始清理
+pub(crate) struct ArtifactConfig {
+    pub(crate) root: PathBuf,
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) max_bytes: Option<u64>,
+}
+
+/// 命令行 `--debug`/`-d` 或环境变量 `BILI_TICKET_GT_DEBUG` 任一开启即进入调试模式
+pub(crate) fn init(flag: bool, config: ArtifactConfig) -> bool {
+    let enabled = flag
         || env::var("BILI_TICKET_GT_DEBUG")
             .map(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
             .unwrap_or(false);
 
     let _ = DEBUG_ENABLED.set(enabled);
+    let _ = ARTIFACT_CONFIG.set(config);
     enabled
 }
 
@@ -23,33 +46,426 @@ pub(crate) fn enabled() -> bool {
     DEBUG_ENABLED.get().copied().unwrap_or(false)
 }
 
-pub(crate) fn save_image(category: &str, image: &DynamicImage) {
-    if !enabled() {
-        return;
+/// 调试产物根目录，相对路径按启动目录解析
+pub(crate) fn artifacts_root() -> PathBuf {
+    let root = ARTIFACT_CONFIG
+        .get()
+        .map(|config| config.root.clone())
+        .unwrap_or_else(|| PathBuf::from("debug_artifacts"));
+    if root.is_absolute() {
+        return root;
     }
+    env::current_dir()
+        .map(|dir| dir.join(&root))
+        .unwrap_or(root)
+}
 
-    let root = match env::current_dir() {
-        Ok(path) => path,
-        Err(error) => {
-            tracing::warn!(error = %error, "无法确定调试图片保存目录");
-            return;
-        }
-    };
-    let directory = root.join("debug_artifacts");
-    if let Err(error) = fs::create_dir_all(&directory) {
-        tracing::warn!(path = %directory.display(), error = %error, "无法创建调试图片目录");
-        return;
-    }
+/// 测试中把调试产物写入临时目录；只设置保存位置，是否记录由各测试的 `AppState.debug` 决定
+#[cfg(test)]
+pub(crate) fn artifacts_root_for_tests() -> PathBuf {
+    let _ = ARTIFACT_CONFIG.set(ArtifactConfig {
+        root: env::temp_dir().join(format!("gt-debug-test-{}", std::process::id())),
+        max_age: None,
+        max_bytes: None,
+    });
+    artifacts_root()
+}
 
-    let timestamp = SystemTime::now()
+fn timestamp_ms() -> u128 {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map(|duration| duration.as_millis())
-        .unwrap_or_default();
+        .unwrap_or_default()
+}
+
+/// 生成进程内唯一的请求 ID
+pub(crate) fn new_request_id() -> String {
     let sequence = ARTIFACT_COUNTER.fetch_add(1, Ordering::Relaxed);
-    let path: PathBuf = directory.join(format!("{category}-{timestamp}-{sequence}.png"));
+    format!("{}-{sequence}", timestamp_ms())
+}
+
+/// 请求 ID 会直接作为目录名和响应头使用，只允许安全字符
+pub(crate) fn is_safe_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// 单个请求的调试产物，结束时写入 `meta.json`
+struct RequestArtifacts {
+    id: String,
+    endpoint: String,
+    dir: PathBuf,
+    started_at: Instant,
+    created_at_ms: u128,
+    inputs: Value,
+    key: Option<String>,
+    timings: Map<String, Value>,
+    images: Vec<String>,
+    outcome: Option<Value>,
+}
+
+impl RequestArtifacts {
+    fn write_meta(&self) {
+        if let Err(error) = fs::create_dir_all(&self.dir) {
+            tracing::warn!(path = %self.dir.display(), error = %error, "无法创建调试产物目录");
+            return;
+        }
+        let meta = json!({
+            "request_id": self.id,
+            "endpoint": self.endpoint,
+            "created_at_ms": self.created_at_ms,
+            "total_ms": self.started_at.elapsed().as_millis(),
+            "inputs": self.inputs,
+            "key": self.key,
+            "timings_ms": self.timings,
+            "images": self.images,
+            "outcome": self.outcome.clone().unwrap_or_else(|| json!({ "status": "panic" })),
+        });
+        let path = self.dir.join("meta.json");
+        let result = serde_json::to_vec_pretty(&meta)
+            .map_err(|error| error.to_string())
+            .and_then(|bytes| fs::write(&path, bytes).map_err(|error| error.to_string()));
+        if let Err(error) = result {
+            tracing::warn!(path = %path.display(), error = %error, "写入调试元数据失败");
+        }
+    }
+}
+
+/// ### 单个请求的调试作用域
+/// - 可以跨线程移动，每段识别逻辑通过 `enter` 在当前线程上记录产物
+/// - 调用 `finish` 时记录结果；未调用就被丢弃（阻塞任务 panic）时记为 panic，两种情况都会写出 `meta.json`
+pub(crate) struct RequestScope {
+    artifacts: Option<RequestArtifacts>,
+}
+
+impl RequestScope {
+    /// enabled 为 false（非调试模式）时返回空作用域，`enter` 直接执行
+    pub(crate) fn new(enabled: bool, request_id: &str, endpoint: &str, inputs: Value) -> Self {
+        if !enabled {
+            return Self { artifacts: None };
+        }
+        let id = if is_safe_id(request_id) {
+            request_id.to_string()
+        } else {
+            new_request_id()
+        };
+        Self {
+            artifacts: Some(RequestArtifacts {
+                dir: artifacts_root().join(&id),
+                id,
+                endpoint: endpoint.to_string(),
+                started_at: Instant::now(),
+                created_at_ms: timestamp_ms(),
+                inputs,
+                key: None,
+                timings: Map::new(),
+                images: Vec::new(),
+                outcome: None,
+            }),
+        }
+    }
+
+    /// 在当前线程上执行一段识别逻辑，期间的图片、key 和耗时记入本请求
+    pub(crate) fn enter<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        let Some(artifacts) = self.artifacts.take() else {
+            return f();
+        };
+        CURRENT_REQUEST.with(|current| *current.borrow_mut() = Some(artifacts));
+        // panic 时同样取回产物，随后作用域被丢弃时写出
+        let _restore = Restore(&mut self.artifacts);
+        f()
+    }
+
+    /// 记录请求结果，error 为空表示成功
+    pub(crate) fn finish(mut self, error: Option<&Error>) {
+        if let Some(artifacts) = self.artifacts.as_mut() {
+            artifacts.outcome = Some(match error {
+                None => json!({ "status": "success" }),
+                Some(error) => json!({ "status": "error", "error": error.to_string() }),
+            });
+        }
+    }
+}
+
+impl Drop for RequestScope {
+    fn drop(&mut self) {
+        if let Some(artifacts) = self.artifacts.take() {
+            artifacts.write_meta();
+        }
+    }
+}
+
+struct Restore<'a>(&'a mut Option<RequestArtifacts>);
+
+impl Drop for Restore<'_> {
+    fn drop(&mut self) {
+        *self.0 = CURRENT_REQUEST.with(|current| current.borrow_mut().take());
+    }
+}
+
+/// ### 在请求作用域内执行识别逻辑
+/// - enabled 为 true 时本次请求的图片、key、耗时和结果都会写入 `{root}/{request_id}/`
+/// - 否则直接执行
+pub(crate) fn with_request<T>(
+    enabled: bool,
+    request_id: &str,
+    endpoint: &str,
+    inputs: Value,
+    f: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let mut scope = RequestScope::new(enabled, request_id, endpoint, inputs);
+    let result = scope.enter(f);
+    scope.finish(result.as_ref().err());
+    result
+}
+
+fn with_current(f: impl FnOnce(&mut RequestArtifacts)) {
+    CURRENT_REQUEST.with(|current| {
+        if let Some(artifacts) = current.borrow_mut().as_mut() {
+            f(artifacts);
+        }
+    });
+}
+
+/// 记录识别得到的 key（点选坐标或滑块距离）
+pub(crate) fn record_key(key: &str) {
+    with_current(|artifacts| artifacts.key = Some(key.to_string()));
+}
+
+/// 记录某个阶段的耗时
+pub(crate) fn record_timing(phase: &str, elapsed: Duration) {
+    with_current(|artifacts| {
+        artifacts
+            .timings
+            .insert(phase.to_string(), json!(elapsed.as_millis()));
+    });
+}
+
+/// ### 保存调试图片
+/// - 只在开启调试的请求作用域内保存，写入本请求的产物目录
+pub(crate) fn save_image(category: &str, image: &DynamicImage) {
+    let mut target = None;
+    with_current(|artifacts| {
+        artifacts.images.push(format!("{category}.png"));
+        target = Some(artifacts.dir.join(format!("{category}.png")));
+    });
+    let Some(path) = target else {
+        return;
+    };
+    if let Some(directory) = path.parent() {
+        if let Err(error) = fs::create_dir_all(directory) {
+            tracing::warn!(path = %directory.display(), error = %error, "无法创建调试图片目录");
+            return;
+        }
+    }
 
     match image.save_with_format(&path, ImageFormat::Png) {
         Ok(()) => tracing::debug!(path = %path.display(), "已保存调试图片"),
         Err(error) => tracing::warn!(path = %path.display(), error = %error, "保存调试图片失败"),
     }
 }
+
+/// 识别结果标注图的产物名，点选和滑块共用，`/debug/artifacts/{request_id}/annotated` 按此查找
+pub(crate) const ANNOTATED: &str = "annotated";
+
+/// 只在开启调试的请求作用域内生成并保存图片，避免非调试模式下白白绘制
+pub(crate) fn save_rendered(category: &str, render: impl FnOnce() -> DynamicImage) {
+    let recording = CURRENT_REQUEST.with(|current| current.borrow().is_some());
+    if recording {
+        save_image(category, &render());
+    }
+}
+
+/// ### 读取某个请求的识别结果标注图
+/// - 返回 None 表示请求不存在或没有走到识别这一步
+pub(crate) fn annotated_image(request_id: &str) -> std::io::Result<Option<Vec<u8>>> {
+    if !is_safe_id(request_id) {
+        return Ok(None);
+    }
+    let path = artifacts_root()
+        .join(request_id)
+        .join(format!("{ANNOTATED}.png"));
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn entry_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// ### 按保留策略清理调试产物
+/// - 先删除超过 max_age 的产物，再从最旧的开始删除直到总大小不超过 max_bytes
+pub(crate) fn enforce_retention() {
+    let Some(config) = ARTIFACT_CONFIG.get() else {
+        return;
+    };
+    prune(&artifacts_root(), config.max_age, config.max_bytes);
+}
+
+fn prune(root: &Path, max_age: Option<Duration>, max_bytes: Option<u64>) {
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    let now = SystemTime::now();
+    let mut kept = Vec::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let modified = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(now);
+        let age = now.duration_since(modified).unwrap_or_default();
+        if max_age.is_some_and(|max_age| age > max_age) {
+            remove_entry(&path);
+        } else {
+            kept.push((modified, entry_size(&path), path));
+        }
+    }
+
+    if let Some(max_bytes) = max_bytes {
+        kept.sort_by_key(|(modified, _, _)| *modified);
+        let mut total: u64 = kept.iter().map(|(_, size, _)| size).sum();
+        for (_, size, path) in &kept {
+            if total <= max_bytes {
+                break;
+            }
+            remove_entry(path);
+            total = total.saturating_sub(*size);
+        }
+    }
+}
+
+fn remove_entry(path: &Path) {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match result {
+        Ok(()) => tracing::debug!(path = %path.display(), "已清理过期调试产物"),
+        Err(error) => tracing::warn!(path = %path.display(), error = %error, "清理调试产物失败"),
+    }
+}
+
+/// 调试产物列表中的一项
+#[derive(Serialize)]
+pub(crate) struct ArtifactSummary {
+    request_id: String,
+    modified_at_ms: u128,
+    size: u64,
+    files: Vec<String>,
+    meta: Option<Value>,
+}
+
+/// 列出所有按请求分组的调试产物，最新的在前
+pub(crate) fn list_artifacts() -> Vec<ArtifactSummary> {
+    let Ok(entries) = fs::read_dir(artifacts_root()) else {
+        return Vec::new();
+    };
+    let mut summaries = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let request_id = entry.file_name().to_str()?.to_string();
+            let modified_at_ms = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_millis())
+                .unwrap_or_default();
+            let mut files = fs::read_dir(&path)
+                .map(|files| {
+                    files
+                        .filter_map(|file| file.ok()?.file_name().into_string().ok())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            files.sort();
+            let meta = fs::read(path.join("meta.json"))
+                .ok()
+                .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+            Some(ArtifactSummary {
+                request_id,
+                modified_at_ms,
+                size: entry_size(&path),
+                files,
+                meta,
+            })
+        })
+        .collect::<Vec<_>>();
+    summaries.sort_by_key(|summary| std::cmp::Reverse(summary.modified_at_ms));
+    summaries
+}
+
+/// ### 调试产物归档的内容
+/// - request_id 为 None 时为全部按请求分组的产物，返回归档内的目录名和本地目录
+/// - 返回 None 表示对应的请求不存在
+pub(crate) fn archive_source(request_id: Option<&str>) -> Option<(String, PathBuf)> {
+    let root = artifacts_root();
+    match request_id {
+        Some(id) => {
+            let dir = root.join(id);
+            (is_safe_id(id) && dir.is_dir()).then(|| (id.to_string(), dir))
+        }
+        None => Some(("debug_artifacts".to_string(), root)),
+    }
+}
+
+/// ### 把目录打包为 tar 写入 writer
+/// - 逐个文件读取写出，不在内存中保留整个归档
+/// - 目录不存在时写出空归档
+pub(crate) fn write_archive<W: Write>(writer: W, name: &str, dir: &Path) -> std::io::Result<()> {
+    let mut builder = tar::Builder::new(writer);
+    if dir.is_dir() {
+        builder.append_dir_all(name, dir)?;
+    }
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_removes_oldest_request_dirs_over_size_limit() {
+        let root = env::temp_dir().join(format!("gt-artifacts-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for id in ["1-0", "2-1", "3-2"] {
+            fs::create_dir_all(root.join(id)).unwrap();
+            fs::write(root.join(id).join("meta.json"), [0u8; 100]).unwrap();
+            // 保证修改时间有先后
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        prune(&root, None, Some(250));
+        assert!(!root.join("1-0").exists());
+        assert!(root.join("2-1").exists());
+        assert!(root.join("3-2").exists());
+
+        prune(&root, Some(Duration::ZERO), None);
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
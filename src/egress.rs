@@ -0,0 +1,333 @@
+// egress.rs
+
+use crate::error::{blocked, Result};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect;
+use reqwest::Url;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::OnceLock;
+
+static POLICY: OnceLock<EgressPolicy> = OnceLock::new();
+
+/// 重定向最多跟随的次数，与 reqwest 默认值一致
+const MAX_REDIRECTS: usize = 10;
+
+/// ### 出站请求策略
+/// - 限制调用方提供的注册地址和上游下发的图片地址，防止借服务探测内网
+/// - 主机白名单为空表示不限制主机，但仍拦截私有、回环和链路本地地址
+pub(crate) struct EgressPolicy {
+    pub(crate) schemes: Vec<String>,
+    pub(crate) register_hosts: Vec<String>,
+    pub(crate) image_hosts: Vec<String>,
+    pub(crate) allow_private: bool,
+}
+
+impl Default for EgressPolicy {
+    fn default() -> Self {
+        Self {
+            schemes: vec!["http".to_string(), "https".to_string()],
+            register_hosts: Vec::new(),
+            image_hosts: Vec::new(),
+            allow_private: false,
+        }
+    }
+}
+
+/// 被检查的地址用途，对应不同的主机白名单
+#[derive(Clone, Copy)]
+pub(crate) enum Target {
+    Register,
+    Image,
+}
+
+pub(crate) fn init(policy: EgressPolicy) {
+    let _ = POLICY.set(policy);
+}
+
+fn policy() -> &'static EgressPolicy {
+    POLICY.get_or_init(EgressPolicy::default)
+}
+
+/// 按全局策略检查地址，不允许时返回 `EGRESS_BLOCKED` 错误
+pub(crate) fn check(url: &str, target: Target) -> Result<()> {
+    policy().check(url, target)
+}
+
+impl EgressPolicy {
+    /// ### 检查一次出站请求
+    /// - 协议必须在白名单内
+    /// - 主机须匹配对应白名单，`*.example.com` 匹配 example.com 的所有子域名
+    /// - 未放开私有网络时，主机本身或其解析结果不能是私有、回环或链路本地地址
+    pub(crate) fn check(&self, url: &str, target: Target) -> Result<()> {
+        let parsed = Url::parse(url).map_err(|_| blocked(&format!("无效的地址: {url}")))?;
+        self.check_scheme_and_ip(&parsed)?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| blocked(&format!("地址缺少主机: {url}")))?;
+
+        let allowlist = match target {
+            Target::Register => &self.register_hosts,
+            Target::Image => &self.image_hosts,
+        };
+        if !allowlist.is_empty() && !allowlist.iter().any(|pattern| host_matches(pattern, host)) {
+            return Err(blocked(&format!("主机不在白名单内: {host}")));
+        }
+
+        if !self.allow_private {
+            let port = parsed.port_or_known_default().unwrap_or(80);
+            // 解析失败交给实际请求报网络错误；请求时的 DNS 解析还会再检查一次，防止 DNS 重绑定
+            if let Ok(addrs) = (host.trim_matches(['[', ']']), port).to_socket_addrs() {
+                if let Some(addr) = addrs.into_iter().find(|addr| is_internal(addr.ip())) {
+                    return Err(blocked(&format!(
+                        "主机解析到内网地址: {host} -> {}",
+                        addr.ip()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_scheme_and_ip(&self, url: &Url) -> Result<()> {
+        if !self
+            .schemes
+            .iter()
+            .any(|scheme| scheme.eq_ignore_ascii_case(url.scheme()))
+        {
+            return Err(blocked(&format!("不允许的协议: {}", url.scheme())));
+        }
+        if !self.allow_private {
+            let ip = url
+                .host_str()
+                .and_then(|host| host.trim_matches(['[', ']']).parse::<IpAddr>().ok());
+            if let Some(ip) = ip.filter(|ip| is_internal(*ip)) {
+                return Err(blocked(&format!("不允许访问内网地址: {ip}")));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 私有、回环、链路本地等不应从服务端访问的地址
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_internal_v4(mapped);
+            }
+            is_internal_v6(ip)
+        }
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        // 0.0.0.0/8 与运营商级 NAT 100.64.0.0/10
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // 唯一本地地址 fc00::/7 与链路本地地址 fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .len()
+            .checked_sub(domain.len())
+            .filter(|&start| start > 0 && host.is_char_boundary(start))
+            .is_some_and(|start| {
+                host[start..].eq_ignore_ascii_case(domain) && host[..start].ends_with('.')
+            }),
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+/// ### 过滤内网地址的 DNS 解析器
+/// - 挂在所有上游客户端上，即使主机名在检查之后被重新解析到内网也无法连接
+/// - 调用方配置的代理主机不受限制：代理通常就在本机或内网，经代理访问的目标地址由 `check` 检查
+pub(crate) struct GuardedResolver {
+    proxy_host: Option<String>,
+}
+
+impl GuardedResolver {
+    pub(crate) fn new(proxy: Option<&str>) -> Self {
+        Self {
+            proxy_host: proxy.and_then(proxy_host),
+        }
+    }
+}
+
+/// 代理地址中的主机名，与 reqwest 一样允许省略协议
+fn proxy_host(proxy: &str) -> Option<String> {
+    let host = |url: Url| {
+        url.host_str()
+            .map(|host| host.trim_matches(['[', ']']).to_string())
+    };
+    Url::parse(proxy)
+        .ok()
+        .and_then(host)
+        .or_else(|| Url::parse(&format!("http://{proxy}")).ok().and_then(host))
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let is_proxy = self
+            .proxy_host
+            .as_deref()
+            .is_some_and(|host| host.eq_ignore_ascii_case(name.as_str()));
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .collect::<Vec<SocketAddr>>();
+            if is_proxy || policy().allow_private {
+                return Ok(Box::new(addrs.into_iter()) as Addrs);
+            }
+            let public = addrs
+                .into_iter()
+                .filter(|addr| !is_internal(addr.ip()))
+                .collect::<Vec<_>>();
+            if public.is_empty() {
+                return Err(blocked(&format!("主机解析到内网地址: {host}")).into());
+            }
+            Ok(Box::new(public.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// 重定向的每一跳同样检查协议和 IP 字面量地址，主机名由 `GuardedResolver` 把关
+pub(crate) fn redirect_policy() -> redirect::Policy {
+    redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("重定向次数过多");
+        }
+        match policy().check_scheme_and_ip(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(e) => attempt.error(e),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientManager;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn blocks_internal_addresses_and_hosts_outside_allowlist() {
+        let policy = EgressPolicy {
+            image_hosts: vec!["*.geetest.com".to_string(), "*.HDSLB.com".to_string()],
+            ..EgressPolicy::default()
+        };
+        let blocked_urls = [
+            "http://127.0.0.1:3000/",
+            "http://10.1.2.3/captcha",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/",
+            "http://[fe80::1]/",
+            "http://[::ffff:192.168.0.1]/",
+            "http://localhost:8080/",
+            "file:///etc/passwd",
+            "ftp://example.com/",
+        ];
+        for url in blocked_urls {
+            let err = policy.check(url, Target::Register).unwrap_err();
+            assert_eq!(err.code(), Some("EGRESS_BLOCKED"), "{url}");
+        }
+
+        assert!(policy
+            .check("https://static.geetest.com/a.png", Target::Image)
+            .is_ok());
+        assert!(policy
+            .check("https://evilgeetest.com/a.png", Target::Image)
+            .is_err());
+        // 主机名和白名单都不区分大小写
+        assert!(policy
+            .check("https://STATIC.GeeTest.com/a.png", Target::Image)
+            .is_ok());
+        assert!(policy
+            .check("https://I0.HDSLB.COM/a.png", Target::Image)
+            .is_ok());
+        assert!(host_matches("*.hdslb.com", "I0.HDSLB.COM"));
+        assert!(!host_matches("*.hdslb.com", "HDSLB.COM"));
+        assert!(!host_matches("*.hdslb.com", "i0.EVILHDSLB.COM"));
+        assert!(policy
+            .check("https://203.0.113.7/a.png", Target::Image)
+            .is_err());
+        assert!(policy
+            .check("https://203.0.113.7/a.png", Target::Register)
+            .is_ok());
+
+        let open = EgressPolicy {
+            allow_private: true,
+            ..EgressPolicy::default()
+        };
+        assert!(open
+            .check("http://127.0.0.1:3000/", Target::Register)
+            .is_ok());
+        assert!(open.check("file:///etc/passwd", Target::Register).is_err());
+    }
+
+    /// 代理主机解析到本机时照常使用，不经代理直连同一地址仍被拦截
+    #[test]
+    fn proxy_host_resolving_to_loopback_is_allowed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let proxy = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 2\r\n\r\nok")
+                .unwrap();
+            request_line
+        });
+
+        let clients = ClientManager::new();
+        let proxied = clients
+            .get(Some(&format!("http://localhost:{port}")), None, None)
+            .unwrap();
+        let response = proxied
+            .get("http://captcha.example.com/get.php")
+            .send()
+            .unwrap();
+        assert_eq!(response.text().unwrap(), "ok");
+        let request_line = proxy.join().unwrap();
+        assert!(
+            request_line.starts_with("GET http://captcha.example.com/get.php "),
+            "{request_line}"
+        );
+
+        let direct = clients.get(None, None, None).unwrap();
+        let err = direct
+            .get(format!("http://localhost:{port}/"))
+            .send()
+            .unwrap_err();
+        assert!(format!("{err:?}").contains("主机解析到内网地址"), "{err:?}");
+    }
+}
@@ -1,4 +1,7 @@
 use std::error::Error as StdError;
+use crate::abstraction::VerifyOutcome;
+use crate::redact;
+use serde::Serialize;
 use std::fmt::{Debug, Display, Formatter};
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -23,21 +26,103 @@ pub(crate) enum Kind {
     NetWorkError,
     MissingParam(String),
     ParseError,
+    /// 出站请求被安全策略拦截
+    Blocked(String),
+    /// 上游接口或图片服务器返回异常
+    Upstream(UpstreamFailure),
+    /// 极验验证未通过
+    VerifyRejected(VerifyOutcome),
+    /// 图片尺寸与预期不符
+    ImageSize(ImageSizeMismatch),
+    /// 图片分块方式自相矛盾
+    InvalidLayout(String),
+    /// 未编译内置识别模型
+    RecognitionNotCompiled,
     Other(String),
 }
 
+/// ### 上游请求失败的详情
+/// - status: HTTP 状态码，连接失败等没有响应时为空
+/// - host: 请求的主机
+/// - endpoint: 请求路径，用于区分极验接口和图片服务器
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct UpstreamFailure {
+    pub(crate) status: Option<u16>,
+    pub(crate) host: String,
+    pub(crate) endpoint: String,
+    pub(crate) reason: String,
+}
+
+/// ### 图片尺寸不符的详情
+/// - image: 图片用途
+/// - expected/actual: （宽，高）
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ImageSizeMismatch {
+    pub(crate) image: &'static str,
+    pub(crate) expected: (u32, u32),
+    pub(crate) actual: (u32, u32),
+}
+
+/// 出站请求被拦截
+pub(crate) const EGRESS_BLOCKED: &str = "EGRESS_BLOCKED";
+/// 上游返回异常状态码、响应类型不符、响应过大或网络不通
+pub(crate) const UPSTREAM_ERROR: &str = "UPSTREAM_ERROR";
+/// 验证结果不正确，可以刷新后重试
+pub(crate) const VERIFY_FAIL: &str = "VERIFY_FAIL";
+/// 请求被极验判定为异常
+pub(crate) const VERIFY_FORBIDDEN: &str = "VERIFY_FORBIDDEN";
+/// challenge 已失效，需要重新注册
+pub(crate) const CHALLENGE_EXPIRED: &str = "CHALLENGE_EXPIRED";
+/// 无法识别的验证结果
+pub(crate) const VERIFY_UNKNOWN: &str = "VERIFY_UNKNOWN";
+/// 图片尺寸与预期不符，通常是上游更换了图片格式
+pub(crate) const IMAGE_SIZE_MISMATCH: &str = "IMAGE_SIZE_MISMATCH";
+/// 滑块图片的分块方式自相矛盾，属于服务自身的配置错误
+pub(crate) const INVALID_LAYOUT: &str = "INVALID_LAYOUT";
+/// 未编译 recognition 特性且没有配置其他识别实现
+pub(crate) const RECOGNITION_NOT_COMPILED: &str = "RECOGNITION_NOT_COMPILED";
+/// 请求体缺失或无法解析，仅 `/v1` 接口返回
+pub(crate) const INVALID_REQUEST: &str = "INVALID_REQUEST";
+/// 验证码类型不支持该操作，仅 `/v1` 接口返回
+pub(crate) const UNSUPPORTED_OPERATION: &str = "UNSUPPORTED_OPERATION";
+/// 其余业务错误，仅 `/v1` 接口返回
+pub(crate) const REQUEST_FAILED: &str = "REQUEST_FAILED";
+/// 服务内部错误，仅 `/v1` 接口返回
+pub(crate) const INTERNAL_ERROR: &str = "INTERNAL_ERROR";
+
+/// 错误信息和错误源可能带有代理凭据或完整的上游 URL，格式化后统一脱敏
 impl Debug for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let raw = if f.alternate() {
+            format!("{:#?}", Unredacted(self))
+        } else {
+            format!("{:?}", Unredacted(self))
+        };
+        f.write_str(&redact::text(&raw))
+    }
+}
+
+struct Unredacted<'a>(&'a Error);
+
+impl Debug for Unredacted<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let inner = &self.0.inner;
         let mut builder = f.debug_struct("bili_ticket极验模块错误");
 
-        builder.field("错误类型", &self.inner.kind);
-        match &self.inner.kind {
+        builder.field("错误类型", &inner.kind);
+        match &inner.kind {
             Kind::NetWorkError => {}
             Kind::MissingParam(s) => {builder.field("信息", s);}
             Kind::ParseError => {}
+            Kind::Blocked(s) => {builder.field("信息", s);}
+            Kind::Upstream(failure) => {builder.field("信息", failure);}
+            Kind::VerifyRejected(outcome) => {builder.field("信息", outcome);}
+            Kind::ImageSize(mismatch) => {builder.field("信息", mismatch);}
+            Kind::InvalidLayout(s) => {builder.field("信息", s);}
+            Kind::RecognitionNotCompiled => {builder.field("信息", &"未编译 recognition 特性，内置识别模型不可用");}
             Kind::Other(s) => {builder.field("信息", s);}
         }
-        if let Some(ref source) = self.inner.source {
+        if let Some(ref source) = inner.source {
             builder.field("源", source);
         }
 
@@ -75,6 +160,30 @@ impl Error {
             inner: Box::new(Inner { kind, source: None }),
         }
     }
+
+    /// 需要调用方区分处理的错误码，出现在 HTTP 响应的 `code` 字段
+    pub(crate) fn code(&self) -> Option<&'static str> {
+        match self.inner.kind {
+            Kind::Blocked(_) => Some(EGRESS_BLOCKED),
+            Kind::Upstream(_) => Some(UPSTREAM_ERROR),
+            Kind::VerifyRejected(VerifyOutcome::Fail) => Some(VERIFY_FAIL),
+            Kind::VerifyRejected(VerifyOutcome::Forbidden) => Some(VERIFY_FORBIDDEN),
+            Kind::VerifyRejected(VerifyOutcome::ChallengeExpired) => Some(CHALLENGE_EXPIRED),
+            Kind::VerifyRejected(_) => Some(VERIFY_UNKNOWN),
+            Kind::ImageSize(_) => Some(IMAGE_SIZE_MISMATCH),
+            Kind::InvalidLayout(_) => Some(INVALID_LAYOUT),
+            Kind::RecognitionNotCompiled => Some(RECOGNITION_NOT_COMPILED),
+            _ => None,
+        }
+    }
+
+    /// 上游异常的详情
+    pub(crate) fn upstream(&self) -> Option<&UpstreamFailure> {
+        match &self.inner.kind {
+            Kind::Upstream(failure) => Some(failure),
+            _ => None,
+        }
+    }
 }
 
 pub(crate) fn net_work_error<E: Into<BoxError>>(e: E) -> Error {
@@ -89,6 +198,64 @@ pub(crate) fn parse_error<E: Into<BoxError>>(e: E) -> Error {
     Error::new(Kind::ParseError, Some(e))
 }
 
+pub(crate) fn blocked(s: &str) -> Error {
+    Error::new_without_source(Kind::Blocked(s.to_string()))
+}
+
+fn upstream_failure(status: Option<u16>, url: &str, reason: &str) -> Kind {
+    let (host, endpoint) = reqwest::Url::parse(url)
+        .map(|url| {
+            (
+                url.host_str().unwrap_or_default().to_string(),
+                url.path().to_string(),
+            )
+        })
+        .unwrap_or_else(|_| (String::new(), url.to_string()));
+    Kind::Upstream(UpstreamFailure {
+        status,
+        host,
+        endpoint,
+        reason: reason.to_string(),
+    })
+}
+
+/// 上游有响应但不符合预期
+pub(crate) fn upstream_error(status: Option<u16>, url: &str, reason: &str) -> Error {
+    Error::new_without_source(upstream_failure(status, url, reason))
+}
+
+/// 请求上游时网络失败，没有拿到响应
+pub(crate) fn upstream_network_error<E: Into<BoxError>>(url: &str, e: E) -> Error {
+    Error::new(upstream_failure(None, url, "网络请求失败"), Some(e))
+}
+
+/// 验证未通过，outcome 不应为 Success
+pub(crate) fn verify_rejected(outcome: VerifyOutcome) -> Error {
+    Error::new_without_source(Kind::VerifyRejected(outcome))
+}
+
+pub(crate) fn image_size_mismatch(
+    image: &'static str,
+    expected: (u32, u32),
+    actual: (u32, u32),
+) -> Error {
+    Error::new_without_source(Kind::ImageSize(ImageSizeMismatch {
+        image,
+        expected,
+        actual,
+    }))
+}
+
+/// 分块方式无法用于还原，按它切图会越界或丢掉部分竖条
+pub(crate) fn invalid_layout(s: &str) -> Error {
+    Error::new_without_source(Kind::InvalidLayout(s.to_string()))
+}
+
+/// 编译时关闭了 recognition 特性，又没有配置远程或人工识别
+pub(crate) fn recognition_not_compiled() -> Error {
+    Error::new_without_source(Kind::RecognitionNotCompiled)
+}
+
 pub(crate) fn other<E: Into<BoxError>>(s: &str, e: E) -> Error {
     Error::new(Kind::Other(s.to_string()), Some(e))
 }
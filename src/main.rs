@@ -2,38 +2,60 @@
 
 use axum::{
     body::Body,
-    extract::State,
+    extract::{Path, State},
     http::{header, HeaderMap, HeaderValue, Request, StatusCode},
     middleware::{self, Next},
     response::{IntoResponse, Json, Response},
-    routing::{get, post},
+    routing::get,
     Router,
 };
 
+use clap::Parser;
 use lru::LruCache;
 use reqwest::blocking::Client;
-use serde::{Deserialize, Serialize};
 use std::num::NonZeroUsize;
-use std::panic::{self, AssertUnwindSafe};
+use std::panic;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-use tokio::net::TcpListener;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::task;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing::Instrument;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
 mod abstraction;
+mod annotate;
+mod api;
+mod cli;
 mod click;
 mod debug;
+mod egress;
+mod entropy;
 mod error;
+mod manual;
+mod recognizer;
+mod redact;
+mod restore;
+mod server;
+mod shutdown;
 mod slide;
+mod solve;
+mod telemetry;
+mod transport;
 mod w;
 
-use crate::abstraction::{Api, GenerateW, Test, VerifyType};
+use crate::api::ApiResponse;
+use crate::cli::{Cli, Command, ServeArgs};
 use crate::click::Click;
+use crate::manual::ManualRecognizer;
+use crate::shutdown::Shutdown;
 use crate::slide::Slide;
+use crate::transport::{LiveTransport, RecordingTransport, Transport};
 
 #[derive(Clone)]
 struct ClientManager {
@@ -43,6 +65,10 @@ struct ClientManager {
 const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36";
 const CLIENT_CACHE_SIZE: usize = 256;
 const INSTANCE_CACHE_SIZE: usize = 127;
+const ARTIFACT_RETENTION_INTERVAL: Duration = Duration::from_secs(600);
+const REQUEST_ID_HEADER: &str = "x-request-id";
+/// 调试产物归档从打包线程流向响应体的缓冲区大小
+const ARCHIVE_BUFFER_SIZE: usize = 64 * 1024;
 
 impl ClientManager {
     fn new() -> Self {
@@ -59,7 +85,10 @@ impl ClientManager {
         user_agent: Option<&str>,
         referer: Option<&str>,
     ) -> Result<Arc<Client>, crate::error::Error> {
-        let proxy_key = proxy.unwrap_or("no_proxy");
+        // 代理地址可能带有账号密码，缓存键中只保留摘要
+        let proxy_key = proxy
+            .map(|proxy| format!("{:x}", md5::compute(proxy)))
+            .unwrap_or_else(|| "no_proxy".to_string());
         // 使用传入的 user_agent 或默认值来生成缓存键
         let ua_key = user_agent.unwrap_or(DEFAULT_USER_AGENT);
         let referer_key = referer.unwrap_or("no_referer");
@@ -83,7 +112,10 @@ impl ClientManager {
             // 设置请求超时
             .timeout(Duration::from_secs(10))
             // 设置连接池空闲超时
-            .pool_idle_timeout(Duration::from_secs(10));
+            .pool_idle_timeout(Duration::from_secs(10))
+            // 拦截解析到内网的主机和指向内网的重定向，代理主机除外
+            .dns_resolver(Arc::new(egress::GuardedResolver::new(proxy)))
+            .redirect(egress::redirect_policy());
 
         if let Some(referer_to_set) = referer {
             let mut headers = HeaderMap::new();
@@ -114,198 +146,84 @@ struct AppState {
     client_manager: ClientManager,
     click_instances: Arc<Mutex<LruCache<String, Click>>>,
     slide_instances: Arc<Mutex<LruCache<String, Slide>>>,
+    /// 上游请求录制根目录，未开启录制时为 None
+    record_dir: Option<Arc<PathBuf>>,
+    record_counter: Arc<AtomicU64>,
+    shutdown: Shutdown,
+    /// 调试模式，关闭时调试产物接口返回 404
+    debug: bool,
+    /// 人工识别队列，未选用人工识别时为 None，/manual 页面和接口返回 404
+    manual: Option<Arc<ManualRecognizer>>,
 }
 
 impl AppState {
-    fn new() -> Self {
+    fn new(record_dir: Option<PathBuf>) -> Self {
         let cache_size = NonZeroUsize::new(INSTANCE_CACHE_SIZE).unwrap();
         Self {
             client_manager: ClientManager::new(),
             click_instances: Arc::new(Mutex::new(LruCache::new(cache_size))),
             slide_instances: Arc::new(Mutex::new(LruCache::new(cache_size))),
+            record_dir: record_dir.map(Arc::new),
+            record_counter: Arc::new(AtomicU64::new(0)),
+            shutdown: Shutdown::default(),
+            debug: debug::enabled(),
+            manual: recognizer::manual(),
         }
     }
-}
-
-// 统一请求结构体
-#[derive(Deserialize)]
-struct CommonRequest {
-    gt: String,
-    challenge: String,
-    w: Option<String>,
-    session_id: Option<String>,
-    proxy: Option<String>,
-    image_use_proxy: Option<bool>,
-    user_agent: Option<String>,
-    referer: Option<String>,
-}
-
-#[derive(Deserialize)]
-struct UrlRequest {
-    url: String,
-    session_id: Option<String>,
-    proxy: Option<String>,
-    image_use_proxy: Option<bool>,
-    user_agent: Option<String>,
-    referer: Option<String>,
-}
-
-#[derive(Deserialize)]
-struct GenerateWRequest {
-    key: String,
-    gt: String,
-    challenge: String,
-    c: Vec<u8>,
-    s: String,
-    session_id: Option<String>,
-    proxy: Option<String>,
-    image_use_proxy: Option<bool>,
-    user_agent: Option<String>,
-    referer: Option<String>,
-}
-
-#[derive(Serialize)]
-struct ApiResponse<T> {
-    success: bool,
-    data: Option<T>,
-    error: Option<String>,
-}
-
-#[derive(Serialize)]
-struct TupleResponse2 {
-    first: String,
-    second: String,
-}
-
-#[derive(Serialize)]
-struct CSResponse {
-    c: Vec<u8>,
-    s: String,
-}
 
-impl<T> ApiResponse<T> {
-    fn success(data: T) -> Self {
-        Self {
-            success: true,
-            data: Some(data),
-            error: None,
-        }
-    }
-    fn error(message: String) -> Self {
-        Self {
-            success: false,
-            data: None,
-            error: Some(message),
-        }
+    /// 开启录制时为本次请求创建独立的夹具目录
+    fn recording_transport(&self, category: &str) -> Option<Arc<dyn Transport>> {
+        let root = self.record_dir.as_ref()?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let sequence = self.record_counter.fetch_add(1, Ordering::Relaxed);
+        let dir = root.join(format!("{category}-{timestamp}-{sequence}"));
+        Some(Arc::new(RecordingTransport::new(Arc::new(LiveTransport), dir)))
     }
 }
 
-fn get_click_instance(
-    state: &AppState,
-    session_id: Option<String>,
-    proxy: Option<String>,
-    image_use_proxy: Option<bool>,
-    user_agent: Option<String>,
-    referer: Option<String>,
-) -> Result<Click, Response> {
-    let session_id = session_id.unwrap_or_else(|| "default".to_string());
-    let configured_client = state
-        .client_manager
-        .get(proxy.as_deref(), user_agent.as_deref(), referer.as_deref())
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error(e.to_string())),
-            )
-                .into_response()
-        })?;
-    let download_client = if image_use_proxy.unwrap_or(false) {
-        Arc::clone(&configured_client)
-    } else {
-        state.client_manager.get(None, None, None).map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error(e.to_string())),
-            )
-                .into_response()
-        })?
-    };
-    let mut instances = match state.click_instances.lock() {
-        Ok(guard) => guard,
-        Err(_) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error(
-                    "内部服务错误: Mutex poisoned".to_string(),
-                )),
-            )
-                .into_response())
-        }
-    };
-    if let Some(instance) = instances.get_mut(&session_id) {
-        instance.update_clients(
-            Arc::clone(&configured_client),
-            Arc::clone(&download_client),
-        );
-        return Ok(instance.clone());
+tokio::task_local! {
+    /// 当前 HTTP 请求的 ID，由 `propagate_request_id` 中间件设置
+    static REQUEST_ID: String;
+}
+
+fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// ### 请求 ID 中间件
+/// - 沿用调用方传入的 `X-Request-Id`，缺失或不合法时生成新的
+/// - 后续日志都挂在带 request_id 的 span 下，并在响应头中返回
+async fn propagate_request_id(req: Request<Body>, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| debug::is_safe_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(debug::new_request_id);
+    let span = tracing::info_span!("request_id", request_id = %request_id);
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(req))
+        .instrument(span)
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
     }
-    let new_instance = Click::new(Arc::clone(&configured_client), Arc::clone(&download_client));
-    instances.put(session_id, new_instance.clone());
-    Ok(new_instance)
+    response
 }
 
-fn get_slide_instance(
-    state: &AppState,
-    session_id: Option<String>,
-    proxy: Option<String>,
-    image_use_proxy: Option<bool>,
-    user_agent: Option<String>,
-    referer: Option<String>,
-) -> Result<Slide, Response> {
-    let session_id = session_id.unwrap_or_else(|| "default".to_string());
-    let configured_client = state
-        .client_manager
-        .get(proxy.as_deref(), user_agent.as_deref(), referer.as_deref())
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error(e.to_string())),
-            )
-                .into_response()
-        })?;
-    let download_client = if image_use_proxy.unwrap_or(false) {
-        Arc::clone(&configured_client)
-    } else {
-        state.client_manager.get(None, None, None).map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error(e.to_string())),
-            )
-                .into_response()
-        })?
-    };
-    let mut instances = match state.slide_instances.lock() {
-        Ok(guard) => guard,
-        Err(_) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error(
-                    "内部服务错误: Mutex poisoned".to_string(),
-                )),
-            )
-                .into_response())
-        }
-    };
-    if let Some(instance) = instances.get_mut(&session_id) {
-        instance.update_clients(
-            Arc::clone(&configured_client),
-            Arc::clone(&download_client),
-        );
-        return Ok(instance.clone());
-    }
-    let new_instance = Slide::new(Arc::clone(&configured_client), Arc::clone(&download_client));
-    instances.put(session_id, new_instance.clone());
-    Ok(new_instance)
+/// 登记正在处理的请求，停机排空超时时据此记录被中止的请求
+async fn track_in_flight(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let request_id = current_request_id().unwrap_or_default();
+    let _guard = state.shutdown.track(request_id, req.uri().path().to_string());
+    next.run(req).await
 }
 
 // 新增：一个记录请求体的中间件
@@ -338,343 +256,139 @@ async fn log_request_body(req: Request<Body>, next: Next) -> Result<Response, St
     Ok(response)
 }
 
-macro_rules! handle_blocking_call {
-    ($instance_result:expr, $block:expr) => {{
-        let mut instance = match $instance_result {
-            Ok(inst) => inst,
-            Err(resp) => return resp,
-        };
-        match task::spawn_blocking(move || {
-            panic::catch_unwind(AssertUnwindSafe(|| $block(&mut instance)))
-        })
-        .await
-        {
-            Ok(Ok(Ok(data))) => Json(ApiResponse::success(data)).into_response(),
-            Ok(Ok(Err(e))) => {
-                tracing::error!("业务逻辑错误: {}", e);
-                (
-                    StatusCode::BAD_REQUEST,
-                    Json(ApiResponse::<()>::error(e.to_string())),
-                )
-                    .into_response()
-            }
-            Ok(Err(e)) => {
-                tracing::error!(
-                    panic_payload = ?e,
-                    "阻塞业务任务发生 panic"
-                );
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error(
-                        "内部服务错误: 阻塞任务 panic".to_string(),
-                    )),
-                )
-                    .into_response()
-            }
-            Err(e) => {
-                tracing::error!("Tokio 任务执行错误: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error(e.to_string())),
-                )
-                    .into_response()
-            }
-        }
-    }};
-}
-
-// --- API 处理函数 ---
-
-async fn click_simple_match(
-    State(state): State<AppState>,
-    Json(req): Json<CommonRequest>,
-) -> Response {
-    handle_blocking_call!(
-        get_click_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Click| instance.simple_match(&req.gt, &req.challenge)
-    )
-}
-
-async fn click_simple_match_retry(
-    State(state): State<AppState>,
-    Json(req): Json<CommonRequest>,
-) -> Response {
-    handle_blocking_call!(
-        get_click_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Click| instance.simple_match_retry(&req.gt, &req.challenge)
-    )
-}
-
-async fn click_register_test(
-    State(state): State<AppState>,
-    Json(req): Json<UrlRequest>,
-) -> Response {
-    handle_blocking_call!(
-        get_click_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Click| instance
-            .register_test(&req.url)
-            .map(|(f, s)| TupleResponse2 {
-                first: f,
-                second: s
-            })
-    )
-}
-
-async fn click_get_c_s(State(state): State<AppState>, Json(req): Json<CommonRequest>) -> Response {
-    let w_owned = req.w.clone();
-    handle_blocking_call!(
-        get_click_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Click| instance
-            .get_c_s(&req.gt, &req.challenge, w_owned.as_deref())
-            .map(|(c, s)| CSResponse { c, s })
-    )
-}
-
-async fn click_get_type(State(state): State<AppState>, Json(req): Json<CommonRequest>) -> Response {
-    let w_owned = req.w.clone();
-    handle_blocking_call!(
-        get_click_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Click| instance
-            .get_type(&req.gt, &req.challenge, w_owned.as_deref())
-            .map(|t| match t {
-                VerifyType::Click => "click".to_string(),
-                VerifyType::Slide => "slide".to_string(),
-            })
-    )
+async fn health_check() -> &'static str {
+    "OK"
 }
 
-async fn click_verify(State(state): State<AppState>, Json(req): Json<CommonRequest>) -> Response {
-    let w_owned = req.w.clone();
-    handle_blocking_call!(
-        get_click_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Click| instance
-            .verify(&req.gt, &req.challenge, w_owned.as_deref())
-            .map(|(f, s)| TupleResponse2 {
-                first: f,
-                second: s
-            })
-    )
+/// 就绪检查：收到停机信号后返回 503，便于负载均衡摘除流量
+async fn readiness_check(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    if state.shutdown.is_draining() {
+        (StatusCode::SERVICE_UNAVAILABLE, "SHUTTING_DOWN")
+    } else {
+        (StatusCode::OK, "READY")
+    }
 }
 
-async fn click_generate_w(
-    State(state): State<AppState>,
-    Json(req): Json<GenerateWRequest>,
-) -> Response {
-    handle_blocking_call!(
-        get_click_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Click| instance.generate_w(
-            &req.key,
-            &req.gt,
-            &req.challenge,
-            &req.c,
-            &req.s
+/// 列出按请求分组的调试产物
+async fn list_debug_artifacts(State(state): State<AppState>) -> Response {
+    if !state.debug {
+        return debug_disabled();
+    }
+    match task::spawn_blocking(debug::list_artifacts).await {
+        Ok(artifacts) => Json(ApiResponse::success(artifacts)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
         )
-    )
+            .into_response(),
+    }
 }
 
-async fn click_test(State(state): State<AppState>, Json(req): Json<UrlRequest>) -> Response {
-    handle_blocking_call!(
-        get_click_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Click| instance.test(&req.url)
-    )
+/// 下载全部调试产物的 tar 归档
+async fn download_all_debug_artifacts(State(state): State<AppState>) -> Response {
+    download_debug_archive(state, None).await
 }
 
-async fn slide_register_test(
+/// 下载单个请求调试产物的 tar 归档
+async fn download_debug_artifacts(
     State(state): State<AppState>,
-    Json(req): Json<UrlRequest>,
+    Path(request_id): Path<String>,
 ) -> Response {
-    handle_blocking_call!(
-        get_slide_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Slide| instance
-            .register_test(&req.url)
-            .map(|(f, s)| TupleResponse2 {
-                first: f,
-                second: s
-            })
-    )
-}
-
-async fn slide_get_c_s(State(state): State<AppState>, Json(req): Json<CommonRequest>) -> Response {
-    let w_owned = req.w.clone();
-    handle_blocking_call!(
-        get_slide_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Slide| instance
-            .get_c_s(&req.gt, &req.challenge, w_owned.as_deref())
-            .map(|(c, s)| CSResponse { c, s })
-    )
+    download_debug_archive(state, Some(request_id)).await
 }
 
-async fn slide_get_type(State(state): State<AppState>, Json(req): Json<CommonRequest>) -> Response {
-    let w_owned = req.w.clone();
-    handle_blocking_call!(
-        get_slide_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Slide| instance
-            .get_type(&req.gt, &req.challenge, w_owned.as_deref())
-            .map(|t| match t {
-                VerifyType::Click => "click".to_string(),
-                VerifyType::Slide => "slide".to_string(),
-            })
-    )
-}
-
-async fn slide_verify(State(state): State<AppState>, Json(req): Json<CommonRequest>) -> Response {
-    let w_owned = req.w.clone();
-    handle_blocking_call!(
-        get_slide_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Slide| instance
-            .verify(&req.gt, &req.challenge, w_owned.as_deref())
-            .map(|(f, s)| TupleResponse2 {
-                first: f,
-                second: s
-            })
+async fn download_debug_archive(state: AppState, request_id: Option<String>) -> Response {
+    if !state.debug {
+        return debug_disabled();
+    }
+    let file_name = format!("{}.tar", request_id.as_deref().unwrap_or("debug_artifacts"));
+    let Some((name, dir)) = debug::archive_source(request_id.as_deref()) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("调试产物不存在".to_string())),
+        )
+            .into_response();
+    };
+    // 边打包边发送，产物总量再大也只占用管道缓冲区大小的内存
+    let (reader, writer) = tokio::io::duplex(ARCHIVE_BUFFER_SIZE);
+    let writer = SyncIoBridge::new(writer);
+    task::spawn_blocking(move || {
+        if let Err(e) = debug::write_archive(writer, &name, &dir) {
+            tracing::warn!(error = %e, "调试产物打包中断");
+        }
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(reader)),
     )
+        .into_response()
 }
 
-async fn slide_generate_w(
+/// 查看单个请求的识别结果标注图
+async fn annotated_debug_image(
     State(state): State<AppState>,
-    Json(req): Json<GenerateWRequest>,
+    Path(request_id): Path<String>,
 ) -> Response {
-    handle_blocking_call!(
-        get_slide_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Slide| instance.generate_w(
-            &req.key,
-            &req.gt,
-            &req.challenge,
-            &req.c,
-            &req.s
+    if !state.debug {
+        return debug_disabled();
+    }
+    match task::spawn_blocking(move || debug::annotated_image(&request_id)).await {
+        Ok(Ok(Some(image))) => ([(header::CONTENT_TYPE, "image/png")], image).into_response(),
+        Ok(Ok(None)) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("标注图不存在".to_string())),
         )
-    )
+            .into_response(),
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
 }
 
-async fn slide_test(State(state): State<AppState>, Json(req): Json<UrlRequest>) -> Response {
-    handle_blocking_call!(
-        get_slide_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Slide| instance.test(&req.url)
+fn debug_disabled() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error("调试模式未开启".to_string())),
     )
+        .into_response()
 }
 
-async fn slide_simple_match(
-    State(state): State<AppState>,
-    Json(req): Json<CommonRequest>,
-) -> Response {
-    handle_blocking_call!(
-        get_slide_instance(
-            &state,
-            req.session_id,
-            req.proxy,
-            req.image_use_proxy,
-            req.user_agent,
-            req.referer,
-        ),
-        move |instance: &mut Slide| instance
-            .simple_match(&req.gt, &req.challenge)
-            .map(|(f, s)| TupleResponse2 {
-                first: f,
-                second: s
-            })
-    )
-}
-
-async fn health_check() -> &'static str {
-    "OK"
+/// 带全部中间件的路由，测试中直接调用
+fn app(state: AppState) -> Router {
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/ready", get(readiness_check))
+        .route("/debug/artifacts", get(list_debug_artifacts))
+        .route("/debug/artifacts/archive", get(download_all_debug_artifacts))
+        .route("/debug/artifacts/:request_id/archive", get(download_debug_artifacts))
+        .route("/debug/artifacts/:request_id/annotated", get(annotated_debug_image))
+        .merge(api::routes())
+        .merge(manual::routes())
+        .layer(
+            ServiceBuilder::new()
+                .layer(middleware::from_fn(propagate_request_id))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    track_in_flight,
+                ))
+                .layer(TraceLayer::new_for_http())
+                .layer(middleware::from_fn(log_request_body)) // 应用日志中间件
+                .layer(CorsLayer::permissive()),
+        )
+        .with_state(state)
 }
 
 fn install_panic_hook() {
@@ -685,9 +399,17 @@ fn install_panic_hook() {
     }));
 }
 
-#[tokio::main]
-async fn main() {
-    let debug_mode = debug::init_from_startup_options();
+fn main() -> ExitCode {
+    let mut cli = Cli::parse();
+    let debug_mode = debug::init(cli.debug, cli.artifacts.config());
+    redact::init(cli.log_sensitive);
+    egress::init(cli.egress.policy());
+    transport::set_max_image_bytes(cli.max_image_size_kb * 1024);
+    transport::set_image_host_timeout(Duration::from_secs(cli.image_host_timeout_secs));
+    if let Err(e) = recognizer::init(cli.recognizer.config()) {
+        eprintln!("识别实现初始化失败: {e}");
+        return ExitCode::FAILURE;
+    }
 
     let filter = if debug_mode {
         tracing_subscriber::EnvFilter::new("bili_ticket_gt_server=debug,tower_http=debug")
@@ -696,67 +418,125 @@ async fn main() {
             .unwrap_or_else(|_| "bili_ticket_gt_server=info,tower_http=info".into())
     };
 
-    tracing_subscriber::registry()
-        .with(
-            filter,
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    // 命令行子命令把结果打印到标准输出，日志改走标准错误以免混在一起
+    let serve_mode = matches!(cli.command, None | Some(Command::Serve(_)));
+    let writer = if serve_mode {
+        BoxMakeWriter::new(std::io::stdout)
+    } else {
+        BoxMakeWriter::new(std::io::stderr)
+    };
+
+    let telemetry = match telemetry::init(
+        filter,
+        writer,
+        cli.log_format,
+        cli.otlp_endpoint.as_deref(),
+    ) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("日志初始化失败: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
 
     install_panic_hook();
 
+    let exit_code = match cli
+        .command
+        .take()
+        .unwrap_or_else(|| Command::Serve(ServeArgs::default()))
+    {
+        Command::Serve(args) => serve(args, cli.record_dir.take(), debug_mode),
+        Command::Tool(command) => match cli::run(command, &cli) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{e}");
+                ExitCode::FAILURE
+            }
+        },
+    };
+    // 退出前把缓冲中的 span 发送出去
+    telemetry.shutdown();
+    exit_code
+}
+
+fn serve(args: ServeArgs, record_dir: Option<PathBuf>, debug_mode: bool) -> ExitCode {
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            tracing::error!(error = %e, "tokio 运行时创建失败");
+            return ExitCode::FAILURE;
+        }
+    };
+    let exit_code = runtime.block_on(run_server(args, record_dir, debug_mode));
+    // 排空超时后仍在运行的阻塞任务无法取消，不再等待它们结束
+    runtime.shutdown_background();
+    exit_code
+}
+
+async fn run_server(args: ServeArgs, record_dir: Option<PathBuf>, debug_mode: bool) -> ExitCode {
+    if let Err(e) = recognizer::ensure_available() {
+        tracing::warn!(error = %e, "识别接口不可用，仅提供 generate_w、get_c_s、get_type 和 verify 等接口");
+    }
     if debug_mode {
         let current_dir = std::env::current_dir()
-            .map(|path| path.display().to_string())
+            .map(|path| path.join("models").display().to_string())
             .unwrap_or_else(|error| format!("<无法读取: {error}>"));
         tracing::info!(
-            current_dir = %current_dir,
-            model_dir = %format!("{current_dir}\\models"),
-            artifacts_dir = %format!("{current_dir}\\debug_artifacts"),
+            model_dir = %current_dir,
+            artifacts_dir = %debug::artifacts_root().display(),
             "调试模式已开启"
         );
+        // 启动时先清理一次，之后定期按保留策略清理
+        tokio::spawn(async {
+            let mut interval = tokio::time::interval(ARTIFACT_RETENTION_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = task::spawn_blocking(debug::enforce_retention).await {
+                    tracing::warn!(error = %e, "调试产物清理任务执行失败");
+                }
+            }
+        });
     }
 
-    let state = AppState::new();
+    if let Some(dir) = &record_dir {
+        tracing::info!(record_dir = %dir.display(), "上游请求录制已开启");
+    }
 
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/click/simple_match", post(click_simple_match))
-        .route("/click/simple_match_retry", post(click_simple_match_retry))
-        .route("/click/register_test", post(click_register_test))
-        .route("/click/get_c_s", post(click_get_c_s))
-        .route("/click/get_type", post(click_get_type))
-        .route("/click/verify", post(click_verify))
-        .route("/click/generate_w", post(click_generate_w))
-        .route("/click/test", post(click_test))
-        .route("/slide/register_test", post(slide_register_test))
-        .route("/slide/get_c_s", post(slide_get_c_s))
-        .route("/slide/get_type", post(slide_get_type))
-        .route("/slide/verify", post(slide_verify))
-        .route("/slide/generate_w", post(slide_generate_w))
-        .route("/slide/test", post(slide_test))
-        .route("/slide/simple_match", post(slide_simple_match))
-        .layer(
-            ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
-                .layer(middleware::from_fn(log_request_body)) // 应用日志中间件
-                .layer(CorsLayer::permissive()),
-        )
-        .with_state(state);
+    let state = AppState::new(record_dir);
+    let shutdown = state.shutdown.clone();
 
-    let bind_addr = "0.0.0.0:3000";
-    let listener = match TcpListener::bind(bind_addr).await {
-        Ok(listener) => listener,
+    let app = app(state);
+
+    let listeners = match server::bind(&args).await {
+        Ok(listeners) => listeners,
         Err(e) => {
-            tracing::error!(address = bind_addr, error = %e, "端口绑定失败，服务启动终止");
-            std::process::exit(1);
+            tracing::error!(error = %e, "监听器创建失败，服务启动终止");
+            return ExitCode::FAILURE;
         }
     };
 
-    tracing::info!(address = bind_addr, "HTTP 服务已启动");
+    for listener in &listeners {
+        tracing::info!(address = %listener, "HTTP 服务已启动");
+    }
 
-    if let Err(e) = axum::serve(listener, app).await {
-        tracing::error!(error = %e, "HTTP 监听任务退出，进程即将终止");
-        std::process::exit(1);
+    let server = server::serve(listeners, app, shutdown.clone().wait_for_signal());
+    tokio::select! {
+        _ = server => {
+            tracing::info!("处理中的请求已全部完成，服务已停止");
+            ExitCode::SUCCESS
+        }
+        _ = shutdown.drain_deadline(Duration::from_secs(args.drain_timeout_secs)) => {
+            let aborted = shutdown.log_aborted();
+            tracing::warn!(
+                aborted,
+                drain_timeout_secs = args.drain_timeout_secs,
+                "排空超时，强制停止服务"
+            );
+            ExitCode::FAILURE
+        }
     }
 }
@@ -0,0 +1,524 @@
+// manual.rs
+
+use crate::api::ApiResponse;
+use crate::error::{other_without_source, Error, Result};
+use crate::recognizer::{encode_png, ClickRecognizer, SlideRecognizer};
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+
+/// 人工识别的默认等待时间
+pub(crate) const DEFAULT_MANUAL_TIMEOUT: Duration = Duration::from_secs(120);
+/// 在线程中同步等待答复时检查的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+const QUEUE_PAGE: &str = include_str!("manual/queue.html");
+const CLICK_PAGE: &str = include_str!("manual/click.html");
+const SLIDE_PAGE: &str = include_str!("manual/slide.html");
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum TaskKind {
+    Click,
+    Slide,
+}
+
+/// 人工提交的结果，点选为按顺序的像素坐标，滑块为滑动距离
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Answer {
+    Click { points: Vec<(f32, f32)> },
+    Slide { x: u32 },
+}
+
+impl Answer {
+    /// 点选任务的结果
+    pub(crate) fn points(self) -> Result<Vec<(f32, f32)>> {
+        match self {
+            Answer::Click { points } => Ok(points),
+            Answer::Slide { .. } => Err(other_without_source("人工识别结果类型不符")),
+        }
+    }
+
+    /// 滑块任务的结果
+    pub(crate) fn offset(self) -> Result<u32> {
+        match self {
+            Answer::Slide { x } => Ok(x),
+            Answer::Click { .. } => Err(other_without_source("人工识别结果类型不符")),
+        }
+    }
+}
+
+/// 超过等待时间仍未提交
+pub(crate) fn timed_out() -> Error {
+    other_without_source("人工识别超时")
+}
+
+/// ### 已入队、等待人工提交的任务
+/// - 服务端用 `tokio::time::timeout` 异步等待 receiver，不占用阻塞线程
+/// - receiver 被丢弃（超时或请求断开）后任务自动出队
+pub(crate) struct Parked {
+    pub(crate) timeout: Duration,
+    pub(crate) receiver: oneshot::Receiver<Answer>,
+}
+
+impl Parked {
+    /// 在当前线程等待答复，供命令行等不在异步运行时中的场景使用，超时返回 None
+    pub(crate) fn blocking_answer(mut self) -> Option<Answer> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.receiver.try_recv() {
+                Ok(answer) => return Some(answer),
+                Err(TryRecvError::Closed) => return None,
+                Err(TryRecvError::Empty) if Instant::now() >= deadline => return None,
+                Err(TryRecvError::Empty) => std::thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+}
+
+/// 等待人工处理的识别任务
+struct Pending {
+    kind: TaskKind,
+    /// 图片名和 PNG 数据，点选为 picture，滑块为 background 和 piece
+    images: Vec<(&'static str, Vec<u8>)>,
+    /// 用于校验提交的坐标：点选为原图尺寸，滑块为背景图宽度减去滑块宽度
+    bounds: (u32, u32),
+    created_at_ms: u128,
+    deadline: Instant,
+    answer: oneshot::Sender<Answer>,
+}
+
+/// 队列页面中的一项
+#[derive(Serialize)]
+struct TaskSummary {
+    id: u64,
+    kind: TaskKind,
+    created_at_ms: u128,
+    remaining_secs: u64,
+}
+
+/// ### 人工识别
+/// - 识别时把图片放进队列并返回 `Parked`，由人在内置页面上点选或拖动滑块后提交
+/// - 超过 timeout 仍未提交时任务出队，本次识别失败
+pub(crate) struct ManualRecognizer {
+    timeout: Duration,
+    next_id: AtomicU64,
+    pending: Mutex<BTreeMap<u64, Pending>>,
+}
+
+impl ManualRecognizer {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// 等待方已放弃（超时或请求断开）的任务在取用时出队
+    fn tasks(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, Pending>> {
+        let mut tasks = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|_, task| !task.answer.is_closed());
+        tasks
+    }
+
+    /// 入队后立即返回，答复通过 `Parked` 送达
+    fn park(
+        &self,
+        kind: TaskKind,
+        images: Vec<(&'static str, &DynamicImage)>,
+        bounds: (u32, u32),
+    ) -> Result<Parked> {
+        let images = images
+            .into_iter()
+            .map(|(name, image)| Ok((name, encode_png(image)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let (answer, receiver) = oneshot::channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let created_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        self.tasks().insert(
+            id,
+            Pending {
+                kind,
+                images,
+                bounds,
+                created_at_ms,
+                deadline: Instant::now() + self.timeout,
+                answer,
+            },
+        );
+        tracing::info!(task_id = id, kind = ?kind, "等待人工识别");
+        Ok(Parked {
+            timeout: self.timeout,
+            receiver,
+        })
+    }
+
+    fn park_click(&self, picture: &DynamicImage) -> Result<Parked> {
+        self.park(
+            TaskKind::Click,
+            vec![("picture", picture)],
+            picture.dimensions(),
+        )
+    }
+
+    fn park_slide(&self, background: &DynamicImage, piece: &DynamicImage) -> Result<Parked> {
+        let max_x = background.width().saturating_sub(piece.width());
+        let images = vec![("background", background), ("piece", piece)];
+        self.park(TaskKind::Slide, images, (max_x, 0))
+    }
+
+    fn summaries(&self) -> Vec<TaskSummary> {
+        let now = Instant::now();
+        self.tasks()
+            .iter()
+            .map(|(&id, task)| TaskSummary {
+                id,
+                kind: task.kind,
+                created_at_ms: task.created_at_ms,
+                remaining_secs: task.deadline.saturating_duration_since(now).as_secs(),
+            })
+            .collect()
+    }
+
+    fn kind(&self, id: u64) -> Option<TaskKind> {
+        self.tasks().get(&id).map(|task| task.kind)
+    }
+
+    fn image(&self, id: u64, name: &str) -> Option<Vec<u8>> {
+        let tasks = self.tasks();
+        let (_, bytes) = tasks.get(&id)?.images.iter().find(|(n, _)| *n == name)?;
+        Some(bytes.clone())
+    }
+
+    /// ### 提交人工识别结果
+    /// - 任务不存在（已超时或已提交）时返回 None
+    /// - 结果类型或坐标范围不符时返回错误说明，任务保留在队列中
+    fn answer(&self, id: u64, answer: Answer) -> Option<std::result::Result<(), String>> {
+        let mut tasks = self.tasks();
+        let task = tasks.get(&id)?;
+        let (width, height) = task.bounds;
+        let valid = match (&answer, task.kind) {
+            (Answer::Click { points }, TaskKind::Click) => {
+                !points.is_empty()
+                    && points.iter().all(|&(x, y)| {
+                        (0.0..=width as f32).contains(&x) && (0.0..=height as f32).contains(&y)
+                    })
+            }
+            (Answer::Slide { x }, TaskKind::Slide) => *x <= width,
+            _ => return Some(Err("提交的结果与任务类型不符".to_string())),
+        };
+        if !valid {
+            return Some(Err("提交的坐标超出图片范围".to_string()));
+        }
+        let task = tasks.remove(&id)?;
+        // 等待方已超时退出时发送失败，视为任务不存在
+        let sent = task
+            .answer
+            .send(answer)
+            .map_err(|_| "任务已超时".to_string());
+        if sent.is_ok() {
+            tracing::info!(task_id = id, "人工识别已提交");
+        }
+        Some(sent)
+    }
+}
+
+/// 同步识别在当前线程等待答复，识别流程通过 park 入队后异步等待
+impl ClickRecognizer for ManualRecognizer {
+    fn recognize(&self, picture: &DynamicImage) -> Result<Vec<(f32, f32)>> {
+        let parked = self.park_click(picture)?;
+        parked.blocking_answer().ok_or_else(timed_out)?.points()
+    }
+
+    fn park(&self, picture: &DynamicImage) -> Result<Option<Parked>> {
+        self.park_click(picture).map(Some)
+    }
+}
+
+impl SlideRecognizer for ManualRecognizer {
+    fn recognize(&self, background: &DynamicImage, piece: &DynamicImage) -> Result<u32> {
+        let parked = self.park_slide(background, piece)?;
+        parked.blocking_answer().ok_or_else(timed_out)?.offset()
+    }
+
+    fn park(&self, background: &DynamicImage, piece: &DynamicImage) -> Result<Option<Parked>> {
+        self.park_slide(background, piece).map(Some)
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(ApiResponse::<()>::error(message.to_string()))).into_response()
+}
+
+fn manual_disabled() -> Response {
+    error(StatusCode::NOT_FOUND, "人工识别未开启")
+}
+
+fn task_missing() -> Response {
+    error(StatusCode::NOT_FOUND, "任务不存在或已超时")
+}
+
+/// 待处理任务队列页面
+async fn queue_page(State(state): State<AppState>) -> Response {
+    if state.manual.is_none() {
+        return manual_disabled();
+    }
+    Html(QUEUE_PAGE).into_response()
+}
+
+/// 待处理任务列表，最早入队的在前
+async fn list_tasks(State(state): State<AppState>) -> Response {
+    match state.manual {
+        Some(manual) => Json(ApiResponse::success(manual.summaries())).into_response(),
+        None => manual_disabled(),
+    }
+}
+
+/// 单个任务的识别页面
+async fn task_page(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    let Some(manual) = state.manual else {
+        return manual_disabled();
+    };
+    let page = match manual.kind(id) {
+        Some(TaskKind::Click) => CLICK_PAGE,
+        Some(TaskKind::Slide) => SLIDE_PAGE,
+        None => return task_missing(),
+    };
+    Html(page.replace("{{id}}", &id.to_string())).into_response()
+}
+
+async fn task_image(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(u64, String)>,
+) -> Response {
+    let Some(manual) = state.manual else {
+        return manual_disabled();
+    };
+    match manual.image(id, &name) {
+        Some(bytes) => ([(header::CONTENT_TYPE, "image/png")], bytes).into_response(),
+        None => task_missing(),
+    }
+}
+
+/// 提交人工识别结果，点选为 `{"points": [[x, y], ...]}`，滑块为 `{"x": 整数}`
+async fn submit_task(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(answer): Json<Answer>,
+) -> Response {
+    let Some(manual) = state.manual else {
+        return manual_disabled();
+    };
+    match manual.answer(id, answer) {
+        Some(Ok(())) => Json(ApiResponse::success(())).into_response(),
+        Some(Err(message)) => error(StatusCode::BAD_REQUEST, &message),
+        None => task_missing(),
+    }
+}
+
+/// 人工识别相关的页面和接口，未开启人工识别时均返回 404
+pub(crate) fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/manual", get(queue_page))
+        .route("/manual/tasks", get(list_tasks))
+        .route("/manual/tasks/:id", get(task_page).post(submit_task))
+        .route("/manual/tasks/:id/:image", get(task_image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{HeaderMap, Request};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tower::Service;
+
+    async fn call(state: &AppState, request: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let response = crate::app(state.clone()).call(request).await.unwrap();
+        let (status, headers) = (response.status(), response.headers().clone());
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, headers, bytes.to_vec())
+    }
+
+    async fn get(state: &AppState, path: &str) -> (StatusCode, HeaderMap, Vec<u8>) {
+        call(state, Request::get(path).body(Body::empty()).unwrap()).await
+    }
+
+    async fn post(state: &AppState, path: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::post(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, _, bytes) = call(state, request).await;
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    fn wait_for_task(manual: &ManualRecognizer) -> u64 {
+        for _ in 0..200 {
+            if let Some(task) = manual.summaries().first() {
+                return task.id;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("任务未入队");
+    }
+
+    #[test]
+    fn parks_recognition_until_answered_or_timed_out() {
+        let manual = Arc::new(ManualRecognizer::new(Duration::from_secs(10)));
+        let waiting = Arc::clone(&manual);
+        let solve = std::thread::spawn(move || {
+            ClickRecognizer::recognize(waiting.as_ref(), &DynamicImage::new_rgb8(344, 384))
+        });
+        let id = wait_for_task(&manual);
+        assert_eq!(manual.kind(id), Some(TaskKind::Click));
+        assert!(manual.image(id, "picture").is_some());
+        let wrong = manual.answer(id, Answer::Slide { x: 10 });
+        assert!(matches!(wrong, Some(Err(_))));
+        let outside = manual.answer(
+            id,
+            Answer::Click {
+                points: vec![(400.0, 10.0)],
+            },
+        );
+        assert!(matches!(outside, Some(Err(_))));
+        let points = vec![(12.5, 30.0), (200.0, 100.0)];
+        let answered = manual.answer(
+            id,
+            Answer::Click {
+                points: points.clone(),
+            },
+        );
+        assert!(matches!(answered, Some(Ok(()))));
+        assert_eq!(solve.join().unwrap().unwrap(), points);
+        assert!(manual.summaries().is_empty());
+
+        // 等待方放弃（超时或请求断开）后任务出队，再提交时任务已不存在
+        let parked = manual
+            .park_click(&DynamicImage::new_rgb8(344, 384))
+            .unwrap();
+        let id = wait_for_task(&manual);
+        drop(parked);
+        assert!(manual.summaries().is_empty());
+        assert!(manual.answer(id, Answer::Click { points }).is_none());
+
+        let manual = ManualRecognizer::new(Duration::from_millis(50));
+        let background = DynamicImage::new_rgb8(260, 160);
+        let piece = DynamicImage::new_rgba8(60, 160);
+        assert!(SlideRecognizer::recognize(&manual, &background, &piece).is_err());
+        assert!(manual.summaries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn routes_serve_and_answer_parked_tasks() {
+        let off = AppState {
+            manual: None,
+            ..AppState::new(None)
+        };
+        for path in [
+            "/manual",
+            "/manual/tasks",
+            "/manual/tasks/1",
+            "/manual/tasks/1/picture",
+        ] {
+            assert_eq!(get(&off, path).await.0, StatusCode::NOT_FOUND, "{path}");
+        }
+        let (status, body) = post(&off, "/manual/tasks/1", json!({"x": 10})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "人工识别未开启");
+
+        let manual = Arc::new(ManualRecognizer::new(Duration::from_secs(10)));
+        let on = AppState {
+            manual: Some(Arc::clone(&manual)),
+            ..AppState::new(None)
+        };
+        assert_eq!(get(&on, "/manual").await.0, StatusCode::OK);
+
+        let waiting = Arc::clone(&manual);
+        let solve = std::thread::spawn(move || {
+            ClickRecognizer::recognize(waiting.as_ref(), &DynamicImage::new_rgb8(344, 384))
+        });
+        let id = wait_for_task(&manual);
+        let (status, _, bytes) = get(&on, "/manual/tasks").await;
+        assert_eq!(status, StatusCode::OK);
+        let tasks: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(tasks["data"][0]["id"], id);
+        assert_eq!(tasks["data"][0]["kind"], "click");
+
+        let (status, _, page) = get(&on, &format!("/manual/tasks/{id}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            page,
+            CLICK_PAGE.replace("{{id}}", &id.to_string()).into_bytes()
+        );
+        let (status, headers, picture) = get(&on, &format!("/manual/tasks/{id}/picture")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "image/png");
+        assert!(picture.starts_with(b"\x89PNG"));
+        let missing = get(&on, &format!("/manual/tasks/{id}/background")).await;
+        assert_eq!(missing.0, StatusCode::NOT_FOUND);
+
+        // 无标签的 Answer 按字段区分，滑块结果提交给点选任务时被拒绝，任务仍在队列中
+        let (status, body) = post(&on, &format!("/manual/tasks/{id}"), json!({"x": 10})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "提交的结果与任务类型不符");
+        let (status, _) = post(&on, &format!("/manual/tasks/{id}"), json!({"y": 10})).await;
+        assert!(status.is_client_error());
+        let (status, body) = post(
+            &on,
+            &format!("/manual/tasks/{id}"),
+            json!({"points": [[12.5, 30.0], [200.0, 100.0]]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+        assert_eq!(
+            solve.join().unwrap().unwrap(),
+            vec![(12.5, 30.0), (200.0, 100.0)]
+        );
+        assert_eq!(
+            get(&on, &format!("/manual/tasks/{id}")).await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        let waiting = Arc::clone(&manual);
+        let solve = std::thread::spawn(move || {
+            let background = DynamicImage::new_rgb8(260, 160);
+            let piece = DynamicImage::new_rgba8(60, 160);
+            SlideRecognizer::recognize(waiting.as_ref(), &background, &piece)
+        });
+        let id = wait_for_task(&manual);
+        let (status, _, page) = get(&on, &format!("/manual/tasks/{id}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            page,
+            SLIDE_PAGE.replace("{{id}}", &id.to_string()).into_bytes()
+        );
+        let (_, headers, _) = get(&on, &format!("/manual/tasks/{id}/piece")).await;
+        assert_eq!(headers[header::CONTENT_TYPE], "image/png");
+        let (status, _) = post(&on, &format!("/manual/tasks/{id}"), json!({"x": 42})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(solve.join().unwrap().unwrap(), 42);
+    }
+}
@@ -0,0 +1,380 @@
+// recognizer.rs
+
+use crate::error::{
+    other, other_without_source, parse_error, recognition_not_compiled, upstream_error,
+    upstream_network_error, Result,
+};
+use crate::manual::{ManualRecognizer, Parked};
+use crate::transport::{read_limited, MAX_JSON_BYTES};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+#[cfg(feature = "recognition")]
+use captcha_breaker::captcha::{ChineseClick0, Slide0};
+#[cfg(feature = "recognition")]
+use captcha_breaker::environment::CaptchaEnvironment;
+use clap::ValueEnum;
+use image::{DynamicImage, ImageFormat};
+#[cfg(feature = "recognition")]
+use once_cell::sync::Lazy;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Cursor;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+#[cfg(feature = "recognition")]
+static GLOBAL_CLICK_BREAKER: Lazy<Arc<ChineseClick0>> = Lazy::new(|| {
+    tracing::info!("Loading ChineseClick0 ONNX model... This should only happen once.");
+    let env = CaptchaEnvironment::default();
+    let breaker = env.load_captcha_breaker::<ChineseClick0>().unwrap();
+    tracing::info!("Model loaded successfully.");
+    Arc::new(breaker)
+});
+
+static SELECTED: OnceLock<Selected> = OnceLock::new();
+
+/// 远程识别服务的默认超时
+pub(crate) const DEFAULT_REMOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ### 点选识别
+/// - 返回按点击顺序排列的坐标，单位为输入图片的像素
+pub(crate) trait ClickRecognizer: Send + Sync {
+    fn recognize(&self, picture: &DynamicImage) -> Result<Vec<(f32, f32)>>;
+
+    /// 需要等待人工答复的实现在这里入队并返回任务，其余实现返回 None，由调用方直接识别
+    fn park(&self, _picture: &DynamicImage) -> Result<Option<Parked>> {
+        Ok(None)
+    }
+}
+
+/// ### 滑块识别
+/// - background 为已还原的背景图，返回缺口左侧的 x 坐标，即滑动距离
+pub(crate) trait SlideRecognizer: Send + Sync {
+    fn recognize(&self, background: &DynamicImage, piece: &DynamicImage) -> Result<u32>;
+
+    /// 同 `ClickRecognizer::park`
+    fn park(&self, _background: &DynamicImage, _piece: &DynamicImage) -> Result<Option<Parked>> {
+        Ok(None)
+    }
+}
+
+/// 识别实现
+#[derive(Clone, Copy, Default, PartialEq, Debug, ValueEnum)]
+pub(crate) enum RecognizerKind {
+    /// 本地 captcha_breaker 模型，需要编译 recognition 特性
+    #[default]
+    Local,
+    /// 把图片提交给 HTTP 识别服务
+    Remote,
+    /// 在内置页面上由人工识别
+    Manual,
+}
+
+/// ### 识别实现的配置
+/// - remote_url: 远程识别服务地址，kind 为 Remote 时必填
+/// - manual_timeout: 人工识别的最长等待时间
+pub(crate) struct RecognizerConfig {
+    pub(crate) kind: RecognizerKind,
+    pub(crate) remote_url: Option<String>,
+    pub(crate) remote_timeout: Duration,
+    pub(crate) manual_timeout: Duration,
+}
+
+enum Selected {
+    Local,
+    Remote(Arc<RemoteRecognizer>),
+    Manual(Arc<ManualRecognizer>),
+}
+
+/// 启动时选定识别实现，未调用时使用本地模型
+pub(crate) fn init(config: RecognizerConfig) -> Result<()> {
+    let selected = match config.kind {
+        RecognizerKind::Local => Selected::Local,
+        RecognizerKind::Remote => {
+            let url = config
+                .remote_url
+                .ok_or_else(|| other_without_source("使用远程识别服务时必须指定地址"))?;
+            Selected::Remote(Arc::new(RemoteRecognizer::new(
+                &url,
+                config.remote_timeout,
+            )?))
+        }
+        RecognizerKind::Manual => {
+            Selected::Manual(Arc::new(ManualRecognizer::new(config.manual_timeout)))
+        }
+    };
+    let _ = SELECTED.set(selected);
+    Ok(())
+}
+
+/// 当前配置的点选识别实现；本地模型在第一次取用时加载
+pub(crate) fn click() -> Arc<dyn ClickRecognizer> {
+    match SELECTED.get() {
+        Some(Selected::Remote(remote)) => Arc::clone(remote) as Arc<dyn ClickRecognizer>,
+        Some(Selected::Manual(manual)) => Arc::clone(manual) as Arc<dyn ClickRecognizer>,
+        _ => local_click(),
+    }
+}
+
+/// 当前配置的滑块识别实现
+pub(crate) fn slide() -> Arc<dyn SlideRecognizer> {
+    match SELECTED.get() {
+        Some(Selected::Remote(remote)) => Arc::clone(remote) as Arc<dyn SlideRecognizer>,
+        Some(Selected::Manual(manual)) => Arc::clone(manual) as Arc<dyn SlideRecognizer>,
+        _ => local_slide(),
+    }
+}
+
+/// ### 检查识别是否可用
+/// - 未编译 recognition 特性时只能使用远程或人工识别
+/// - 识别接口在请求上游之前先检查，避免白白获取图片后才失败
+pub(crate) fn ensure_available() -> Result<()> {
+    match SELECTED.get() {
+        Some(Selected::Remote(_) | Selected::Manual(_)) => Ok(()),
+        _ if cfg!(feature = "recognition") => Ok(()),
+        _ => Err(recognition_not_compiled()),
+    }
+}
+
+/// 开启人工识别时返回待处理队列
+pub(crate) fn manual() -> Option<Arc<ManualRecognizer>> {
+    match SELECTED.get() {
+        Some(Selected::Manual(manual)) => Some(Arc::clone(manual)),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "recognition")]
+fn local_click() -> Arc<dyn ClickRecognizer> {
+    Arc::new(LocalClick(Arc::clone(&GLOBAL_CLICK_BREAKER)))
+}
+
+#[cfg(feature = "recognition")]
+fn local_slide() -> Arc<dyn SlideRecognizer> {
+    Arc::new(LocalSlide)
+}
+
+#[cfg(not(feature = "recognition"))]
+fn local_click() -> Arc<dyn ClickRecognizer> {
+    Arc::new(NotCompiled)
+}
+
+#[cfg(not(feature = "recognition"))]
+fn local_slide() -> Arc<dyn SlideRecognizer> {
+    Arc::new(NotCompiled)
+}
+
+/// 未编译内置模型时的占位实现，识别总是返回未编译错误
+#[cfg(not(feature = "recognition"))]
+struct NotCompiled;
+
+#[cfg(not(feature = "recognition"))]
+impl ClickRecognizer for NotCompiled {
+    fn recognize(&self, _picture: &DynamicImage) -> Result<Vec<(f32, f32)>> {
+        Err(recognition_not_compiled())
+    }
+}
+
+#[cfg(not(feature = "recognition"))]
+impl SlideRecognizer for NotCompiled {
+    fn recognize(&self, _background: &DynamicImage, _piece: &DynamicImage) -> Result<u32> {
+        Err(recognition_not_compiled())
+    }
+}
+
+/// captcha_breaker 的 ChineseClick0 模型
+#[cfg(feature = "recognition")]
+struct LocalClick(Arc<ChineseClick0>);
+
+#[cfg(feature = "recognition")]
+impl ClickRecognizer for LocalClick {
+    fn recognize(&self, picture: &DynamicImage) -> Result<Vec<(f32, f32)>> {
+        self.0
+            .run(picture)
+            .map_err(|e| other_without_source(&format!("cb模块内部错误: {}", e)))
+    }
+}
+
+/// captcha_breaker 的 Slide0 算法
+#[cfg(feature = "recognition")]
+struct LocalSlide;
+
+#[cfg(feature = "recognition")]
+impl SlideRecognizer for LocalSlide {
+    fn recognize(&self, background: &DynamicImage, piece: &DynamicImage) -> Result<u32> {
+        Slide0::run(piece, background)
+            .map(|result| result.x1)
+            .map_err(|e| other_without_source(&format!("滑块识别内部错误: {}", e)))
+    }
+}
+
+/// ### 远程识别服务
+/// - 点选：`POST {url}/click`，请求体 `{"image": base64 PNG}`，响应 `{"points": [[x, y], ...]}`
+/// - 滑块：`POST {url}/slide`，请求体 `{"background": base64 PNG, "piece": base64 PNG}`，响应 `{"x": 整数}`
+/// - 地址由运维配置，不经过出站限制，可以指向内网
+/// - 响应体与极验接口一样不超过 1 MB
+pub(crate) struct RemoteRecognizer {
+    client: Client,
+    base_url: String,
+}
+
+#[derive(Deserialize)]
+struct ClickResponse {
+    points: Vec<(f32, f32)>,
+}
+
+#[derive(Deserialize)]
+struct SlideResponse {
+    x: u32,
+}
+
+impl RemoteRecognizer {
+    pub(crate) fn new(base_url: &str, timeout: Duration) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| other("构建识别服务客户端失败", e))?;
+        Ok(Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn post<T: for<'de> Deserialize<'de>>(&self, path: &str, body: &Value) -> Result<T> {
+        let url = format!("{}/{path}", self.base_url);
+        let response = self
+            .client
+            .post(&url)
+            .json(body)
+            .send()
+            .map_err(|e| upstream_network_error(&url, e))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(upstream_error(
+                Some(status.as_u16()),
+                &url,
+                "识别服务返回异常",
+            ));
+        }
+        let bytes = read_limited(response, &url, MAX_JSON_BYTES)?;
+        serde_json::from_slice(&bytes).map_err(parse_error)
+    }
+}
+
+/// 把图片编码为 PNG，远程识别服务和人工识别页面共用
+pub(crate) fn encode_png(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    image
+        .write_to(&mut bytes, ImageFormat::Png)
+        .map_err(|e| other("图片编码失败", e))?;
+    Ok(bytes.into_inner())
+}
+
+impl ClickRecognizer for RemoteRecognizer {
+    fn recognize(&self, picture: &DynamicImage) -> Result<Vec<(f32, f32)>> {
+        let body = json!({ "image": STANDARD.encode(encode_png(picture)?) });
+        self.post::<ClickResponse>("click", &body)
+            .map(|response| response.points)
+    }
+}
+
+impl SlideRecognizer for RemoteRecognizer {
+    fn recognize(&self, background: &DynamicImage, piece: &DynamicImage) -> Result<u32> {
+        let body = json!({
+            "background": STANDARD.encode(encode_png(background)?),
+            "piece": STANDARD.encode(encode_png(piece)?),
+        });
+        self.post::<SlideResponse>("slide", &body)
+            .map(|response| response.x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// 依次应答的假识别服务，把收到的请求路径和请求体回传给测试线程
+    fn stub(
+        responses: Vec<(&'static str, &'static str)>,
+    ) -> (String, mpsc::Receiver<(String, Value)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut request_body = vec![0; content_length];
+                reader.read_exact(&mut request_body).unwrap();
+                let path = request_line.split(' ').nth(1).unwrap().to_string();
+                tx.send((path, serde_json::from_slice(&request_body).unwrap()))
+                    .unwrap();
+                let response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: application/json\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                // 客户端发现响应过大时会提前断开，写入失败不影响测试
+                let _ = reader.get_mut().write_all(response.as_bytes());
+            }
+        });
+        (url, rx)
+    }
+
+    #[test]
+    fn remote_recognizer_posts_images_to_configured_service() {
+        // 合法的 JSON，只是超过大小上限
+        let oversized = format!(r#"{{"x": 1{}}}"#, " ".repeat(MAX_JSON_BYTES as usize)).leak();
+        let (url, requests) = stub(vec![
+            ("200 OK", r#"{"points": [[10.5, 20.0], [30.0, 40.25]]}"#),
+            ("200 OK", r#"{"x": 87}"#),
+            ("503 Service Unavailable", "{}"),
+            ("200 OK", oversized),
+        ]);
+        let remote = RemoteRecognizer::new(&url, DEFAULT_REMOTE_TIMEOUT).unwrap();
+        let picture = DynamicImage::new_rgb8(344, 384);
+
+        let points = ClickRecognizer::recognize(&remote, &picture).unwrap();
+        assert_eq!(points, vec![(10.5, 20.0), (30.0, 40.25)]);
+        let (path, body) = requests.recv().unwrap();
+        assert_eq!(path, "/click");
+        let png = STANDARD.decode(body["image"].as_str().unwrap()).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().width(), 344);
+
+        let background = DynamicImage::new_rgb8(260, 160);
+        let piece = DynamicImage::new_rgba8(60, 160);
+        assert_eq!(
+            SlideRecognizer::recognize(&remote, &background, &piece).unwrap(),
+            87
+        );
+        let (path, body) = requests.recv().unwrap();
+        assert_eq!(path, "/slide");
+        assert!(body["background"].is_string() && body["piece"].is_string());
+
+        let err = ClickRecognizer::recognize(&remote, &picture).unwrap_err();
+        assert_eq!(err.upstream().and_then(|failure| failure.status), Some(503));
+
+        let err = SlideRecognizer::recognize(&remote, &background, &piece).unwrap_err();
+        let failure = err.upstream().unwrap();
+        assert_eq!(failure.status, Some(200));
+        assert!(failure.reason.contains("字节上限"), "{err}");
+    }
+}
@@ -0,0 +1,165 @@
+// restore.rs
+
+use crate::error::{image_size_mismatch, invalid_layout, other, Result};
+use image::{DynamicImage, GenericImage, GenericImageView, RgbaImage};
+
+/// ### 乱序背景图的分块方式
+/// - 乱序图由 `order.len() / strips_per_row` 行竖条拼成，每行 `strips_per_row` 条
+/// - 乱序图中每条宽 `source_strip_width`，还原时只取左侧 `strip_width` 像素
+/// - 还原图第 i 条取自乱序图第 `order[i]` 条（按行优先编号）
+pub struct StripLayout {
+    pub strips_per_row: u32,
+    pub source_strip_width: u32,
+    pub strip_width: u32,
+    pub strip_height: u32,
+    pub order: &'static [u32],
+}
+
+/// 极验滑块背景图：312x160 的乱序图还原为 260x160
+pub const GEETEST_SLIDE: StripLayout = StripLayout {
+    strips_per_row: 26,
+    source_strip_width: 12,
+    strip_width: 10,
+    strip_height: 80,
+    order: &[
+        39, 38, 48, 49, 41, 40, 46, 47, 35, 34, 50, 51, 33, 32, 28, 29, 27, 26, 36, 37, 31, 30, 44,
+        45, 43, 42, 12, 13, 23, 22, 14, 15, 21, 20, 8, 9, 25, 24, 6, 7, 3, 2, 0, 1, 11, 10, 4, 5,
+        19, 18, 16, 17,
+    ],
+};
+
+impl StripLayout {
+    /// ### 检查分块方式能否用于还原
+    /// - 每行条数和竖条宽高不为 0，还原时取的宽度不超过乱序图中的条宽
+    /// - order 长度为每行条数的整数倍，且每项都指向乱序图中存在的竖条
+    pub fn validate(&self) -> Result<()> {
+        if self.strips_per_row == 0 || self.strip_width == 0 || self.strip_height == 0 {
+            return Err(invalid_layout("每行条数和竖条宽高不能为 0"));
+        }
+        if self.strip_width > self.source_strip_width {
+            return Err(invalid_layout(&format!(
+                "还原宽度 {} 超过乱序图条宽 {}",
+                self.strip_width, self.source_strip_width
+            )));
+        }
+        let strips = self.order.len();
+        if strips == 0 || !strips.is_multiple_of(self.strips_per_row as usize) {
+            return Err(invalid_layout(&format!(
+                "order 长度 {strips} 不是每行条数 {} 的整数倍",
+                self.strips_per_row
+            )));
+        }
+        if let Some(source) = self.order.iter().find(|&&source| source as usize >= strips) {
+            return Err(invalid_layout(&format!(
+                "order 中的 {source} 超出竖条总数 {strips}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn rows(&self) -> u32 {
+        self.order.len() as u32 / self.strips_per_row
+    }
+
+    /// 乱序图应有的尺寸
+    pub fn source_size(&self) -> (u32, u32) {
+        (
+            self.strips_per_row * self.source_strip_width,
+            self.rows() * self.strip_height,
+        )
+    }
+
+    /// 还原图的尺寸
+    pub fn restored_size(&self) -> (u32, u32) {
+        (
+            self.strips_per_row * self.strip_width,
+            self.rows() * self.strip_height,
+        )
+    }
+
+    /// 第 index 条在宽为 strip_width 的图中的左上角坐标
+    fn position(&self, index: u32, strip_width: u32) -> (u32, u32) {
+        (
+            index % self.strips_per_row * strip_width,
+            index / self.strips_per_row * self.strip_height,
+        )
+    }
+}
+
+/// ### 还原乱序的滑块背景图
+/// - layout 本身有误时返回错误，不会切图越界
+/// - 图片尺寸与 layout 不符时返回 `IMAGE_SIZE_MISMATCH` 错误，不再静默裁剪
+pub fn restore_background(scrambled: &DynamicImage, layout: &StripLayout) -> Result<DynamicImage> {
+    layout.validate()?;
+    let expected = layout.source_size();
+    let actual = scrambled.dimensions();
+    if actual != expected {
+        return Err(image_size_mismatch("滑块背景图", expected, actual));
+    }
+
+    let (width, height) = layout.restored_size();
+    let mut restored = RgbaImage::new(width, height);
+    for (index, &source) in (0u32..).zip(layout.order) {
+        let (x, y) = layout.position(source, layout.source_strip_width);
+        let (new_x, new_y) = layout.position(index, layout.strip_width);
+        let strip = scrambled.view(x, y, layout.strip_width, layout.strip_height);
+        restored
+            .copy_from(&*strip, new_x, new_y)
+            .map_err(|e| other("重组滑块背景图失败", e))?;
+    }
+    Ok(DynamicImage::ImageRgba8(restored))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn fixture(name: &str) -> DynamicImage {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/slide")
+            .join(name);
+        image::open(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+    }
+
+    /// restored.png 为合成的原图，scrambled.png 为按极验分块方式打乱后的结果，每条多出的 2 像素填充为品红
+    #[test]
+    fn restores_golden_background_and_rejects_other_sizes() {
+        let restored = restore_background(&fixture("scrambled.png"), &GEETEST_SLIDE).unwrap();
+        assert_eq!(restored.to_rgba8(), fixture("restored.png").to_rgba8());
+
+        let err =
+            restore_background(&DynamicImage::new_rgba8(260, 160), &GEETEST_SLIDE).unwrap_err();
+        assert_eq!(err.code(), Some(crate::error::IMAGE_SIZE_MISMATCH));
+    }
+
+    #[test]
+    fn rejects_malformed_layouts_before_slicing() {
+        assert!(GEETEST_SLIDE.validate().is_ok());
+        let malformed = [
+            // 指向不存在的竖条
+            StripLayout {
+                strips_per_row: 2,
+                order: &[0, 1, 2, 4],
+                ..GEETEST_SLIDE
+            },
+            // 还原宽度超过乱序图条宽
+            StripLayout {
+                strip_width: 13,
+                ..GEETEST_SLIDE
+            },
+            // 最后一行不完整
+            StripLayout {
+                order: &GEETEST_SLIDE.order[..51],
+                ..GEETEST_SLIDE
+            },
+        ];
+        for layout in malformed {
+            // 图片尺寸按 layout 给出，确保拦下它的是分块方式检查而不是尺寸检查
+            let (width, height) = layout.source_size();
+            let scrambled = DynamicImage::new_rgba8(width, height);
+            let err = restore_background(&scrambled, &layout).unwrap_err();
+            assert_eq!(err.code(), Some(crate::error::INVALID_LAYOUT), "{err}");
+        }
+    }
+}
@@ -0,0 +1,409 @@
+// server.rs
+
+use crate::cli::ServeArgs;
+use crate::error::{other, other_without_source, Result};
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
+use hyper_util::service::TowerToHyperService;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio_rustls::rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+/// 检查证书和私钥文件是否更新的间隔
+const TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// accept 出错（例如文件描述符耗尽）后稍等再继续，避免空转
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// 已绑定的监听器
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Tls(TcpListener, TlsAcceptor),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+/// accept 得到的连接，TLS 握手放到连接自己的任务中完成
+enum Connection {
+    Tcp(TcpStream),
+    Tls(TcpStream, TlsAcceptor),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let local_addr = |listener: &TcpListener| {
+            listener
+                .local_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|_| "<unknown>".to_string())
+        };
+        match self {
+            Listener::Tcp(listener) => write!(f, "http://{}", local_addr(listener)),
+            Listener::Tls(listener, _) => write!(f, "https://{}", local_addr(listener)),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl Listener {
+    async fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => Ok(Connection::Tcp(listener.accept().await?.0)),
+            Listener::Tls(listener, acceptor) => Ok(Connection::Tls(
+                listener.accept().await?.0,
+                acceptor.clone(),
+            )),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Ok(Connection::Unix(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// ### 按启动参数绑定全部监听器
+/// - 未指定任何监听器时使用默认 TCP 地址
+/// - 所有 TLS 监听器共用同一份证书，文件变化后自动重新加载
+pub(crate) async fn bind(args: &ServeArgs) -> Result<Vec<Listener>> {
+    let mut listeners = Vec::new();
+    for addr in args.plain_binds() {
+        listeners.push(Listener::Tcp(bind_tcp(addr).await?));
+    }
+    if !args.tls_bind.is_empty() {
+        let (cert, key) = args
+            .tls_cert
+            .as_deref()
+            .zip(args.tls_key.as_deref())
+            .ok_or_else(|| other_without_source("启用 TLS 需要同时指定 --tls-cert 和 --tls-key"))?;
+        let acceptor = tls_acceptor(cert, key)?;
+        for addr in &args.tls_bind {
+            listeners.push(Listener::Tls(bind_tcp(addr).await?, acceptor.clone()));
+        }
+    }
+    for path in &args.unix_socket {
+        listeners.push(bind_unix(path, args.unix_socket_mode)?);
+    }
+    Ok(listeners)
+}
+
+async fn bind_tcp(addr: &str) -> Result<TcpListener> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| other(&format!("端口绑定失败: {addr}"), e))
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: u32) -> Result<Listener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    // 进程异常退出时会残留套接字文件；仍能连上说明有其它实例在用，不能删除
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(other_without_source(&format!(
+                "Unix 域套接字路径已被普通文件占用: {}",
+                path.display()
+            )));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(other_without_source(&format!(
+                "Unix 域套接字正被其它进程使用: {}",
+                path.display()
+            )));
+        }
+        fs::remove_file(path).map_err(|e| other("无法删除残留的 Unix 域套接字", e))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| other(&format!("无法绑定 Unix 域套接字: {}", path.display()), e))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|e| other("无法设置 Unix 域套接字权限", e))?;
+    Ok(Listener::Unix(listener, path.to_path_buf()))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &Path, _mode: u32) -> Result<Listener> {
+    Err(other_without_source("当前平台不支持 Unix 域套接字"))
+}
+
+/// ### 在全部监听器上提供服务
+/// - signal 完成后停止 accept，并等待已建立的连接处理完当前请求
+pub(crate) async fn serve(
+    listeners: Vec<Listener>,
+    app: Router,
+    signal: impl Future<Output = ()> + Send + 'static,
+) {
+    let (stop_tx, stop_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        signal.await;
+        let _ = stop_tx.send(true);
+    });
+    let tasks = listeners
+        .into_iter()
+        .map(|listener| tokio::spawn(accept_loop(listener, app.clone(), stop_rx.clone())))
+        .collect::<Vec<_>>();
+    for task in tasks {
+        if let Err(e) = task.await {
+            tracing::error!(error = %e, "监听任务异常退出");
+        }
+    }
+}
+
+async fn accept_loop(listener: Listener, app: Router, mut stop: watch::Receiver<bool>) {
+    let graceful = GracefulShutdown::new();
+    loop {
+        let connection = tokio::select! {
+            connection = listener.accept() => connection,
+            _ = stop.wait_for(|stopped| *stopped) => break,
+        };
+        match connection {
+            Ok(connection) => {
+                let watcher = graceful.watcher();
+                let app = app.clone();
+                tokio::spawn(handle_connection(connection, app, watcher));
+            }
+            Err(e) => {
+                tracing::warn!(listener = %listener, error = %e, "接受连接失败");
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+            }
+        }
+    }
+
+    let name = listener.to_string();
+    #[cfg(unix)]
+    if let Listener::Unix(_, path) = &listener {
+        let _ = fs::remove_file(path);
+    }
+    drop(listener);
+    tracing::debug!(listener = %name, connections = graceful.count(), "监听器已关闭，等待连接结束");
+    graceful.shutdown().await;
+}
+
+async fn handle_connection(connection: Connection, app: Router, watcher: Watcher) {
+    match connection {
+        Connection::Tcp(stream) => serve_connection(stream, app, watcher).await,
+        Connection::Tls(stream, acceptor) => {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => serve_connection(stream, app, watcher).await,
+                Ok(Err(e)) => tracing::debug!(error = %e, "TLS 握手失败"),
+                Err(_) => tracing::debug!("TLS 握手超时"),
+            }
+        }
+        #[cfg(unix)]
+        Connection::Unix(stream) => serve_connection(stream, app, watcher).await,
+    }
+}
+
+async fn serve_connection<I>(io: I, app: Router, watcher: Watcher)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let connection = Builder::new(TokioExecutor::new())
+        .serve_connection_with_upgrades(TokioIo::new(io), TowerToHyperService::new(app))
+        .into_owned();
+    if let Err(e) = watcher.watch(connection).await {
+        tracing::debug!(error = %e, "连接异常结束");
+    }
+}
+
+/// ### 自动重新加载的 TLS 证书
+/// - 定期比较证书和私钥文件内容，变化后加载新证书，新连接立即生效
+/// - 新文件无法解析时保留旧证书，避免证书轮换过程中的中间状态导致服务不可用
+#[derive(Debug)]
+struct ReloadingCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<LoadedCert>,
+}
+
+#[derive(Debug)]
+struct LoadedCert {
+    cert_pem: Vec<u8>,
+    key_pem: Vec<u8>,
+    certified_key: Arc<CertifiedKey>,
+}
+
+impl ReloadingCert {
+    fn load(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new(LoadedCert::read(cert_path, key_path)?),
+        })
+    }
+
+    /// ### 文件内容变化时重新加载
+    /// #### 返回值
+    /// - 是否换用了新证书
+    fn reload_if_changed(&self) -> Result<bool> {
+        let cert_pem = fs::read(&self.cert_path).map_err(|e| other("无法读取 TLS 证书", e))?;
+        let key_pem = fs::read(&self.key_path).map_err(|e| other("无法读取 TLS 私钥", e))?;
+        {
+            let current = self
+                .current
+                .read()
+                .map_err(|_| other_without_source("TLS 证书 RwLock poisoned"))?;
+            if current.cert_pem == cert_pem && current.key_pem == key_pem {
+                return Ok(false);
+            }
+        }
+        let loaded = LoadedCert::parse(cert_pem, key_pem)?;
+        *self
+            .current
+            .write()
+            .map_err(|_| other_without_source("TLS 证书 RwLock poisoned"))? = loaded;
+        Ok(true)
+    }
+}
+
+impl LoadedCert {
+    fn read(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        let cert_pem = fs::read(cert_path)
+            .map_err(|e| other(&format!("无法读取 TLS 证书: {}", cert_path.display()), e))?;
+        let key_pem = fs::read(key_path)
+            .map_err(|e| other(&format!("无法读取 TLS 私钥: {}", key_path.display()), e))?;
+        Self::parse(cert_pem, key_pem)
+    }
+
+    fn parse(cert_pem: Vec<u8>, key_pem: Vec<u8>) -> Result<Self> {
+        let certs = CertificateDer::pem_slice_iter(&cert_pem)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| other("TLS 证书解析失败", e))?;
+        if certs.is_empty() {
+            return Err(other_without_source("TLS 证书文件中没有证书"));
+        }
+        let key =
+            PrivateKeyDer::from_pem_slice(&key_pem).map_err(|e| other("TLS 私钥解析失败", e))?;
+        let signing_key =
+            any_supported_type(&key).map_err(|e| other("不支持的 TLS 私钥类型", e))?;
+        Ok(Self {
+            cert_pem,
+            key_pem,
+            certified_key: Arc::new(CertifiedKey::new(certs, signing_key)),
+        })
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.current
+            .read()
+            .ok()
+            .map(|current| Arc::clone(&current.certified_key))
+    }
+}
+
+fn tls_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor> {
+    let resolver = Arc::new(ReloadingCert::load(cert_path, key_path)?);
+    let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| other("TLS 配置失败", e))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TLS_RELOAD_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let resolver = Arc::clone(&resolver);
+            match tokio::task::spawn_blocking(move || resolver.reload_if_changed()).await {
+                Ok(Ok(true)) => tracing::info!("TLS 证书已重新加载"),
+                Ok(Ok(false)) => {}
+                Ok(Err(e)) => tracing::warn!(error = %e, "TLS 证书重新加载失败，继续使用旧证书"),
+                Err(e) => tracing::warn!(error = %e, "TLS 证书重新加载任务执行失败"),
+            }
+        }
+    });
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    fn write_self_signed(cert_path: &Path, key_path: &Path) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(cert_path, cert.cert.pem()).unwrap();
+        fs::write(key_path, cert.key_pair.serialize_pem()).unwrap();
+    }
+
+    #[test]
+    fn tls_certificate_reloads_only_when_files_change() {
+        let dir = std::env::temp_dir().join(format!("gt-tls-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        write_self_signed(&cert_path, &key_path);
+
+        let resolver = ReloadingCert::load(&cert_path, &key_path).unwrap();
+        let first = Arc::clone(&resolver.current.read().unwrap().certified_key);
+        assert!(!resolver.reload_if_changed().unwrap());
+
+        write_self_signed(&cert_path, &key_path);
+        assert!(resolver.reload_if_changed().unwrap());
+        let second = Arc::clone(&resolver.current.read().unwrap().certified_key);
+        assert_ne!(first.cert, second.cert);
+
+        // 写坏的证书不会替换掉正在使用的证书
+        fs::write(&cert_path, b"not a certificate").unwrap();
+        assert!(resolver.reload_if_changed().is_err());
+        assert_eq!(
+            resolver.current.read().unwrap().certified_key.cert,
+            second.cert
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_requests_over_unix_socket_with_configured_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("gt-uds-test-{}.sock", std::process::id()));
+        let listener = bind_unix(&path, 0o600).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        let app = Router::new().route("/health", get(|| async { "OK" }));
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(vec![listener], app, async {
+            let _ = stop_rx.await;
+        }));
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("OK"));
+
+        stop_tx.send(()).unwrap();
+        server.await.unwrap();
+        assert!(!path.exists());
+    }
+}
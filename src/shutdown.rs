@@ -0,0 +1,164 @@
+// shutdown.rs
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// 正在处理中的一个请求
+struct InFlightRequest {
+    request_id: String,
+    path: String,
+    started_at: Instant,
+}
+
+/// ### 优雅停机状态
+/// - 记录正在处理的请求，收到停机信号后 `/ready` 返回未就绪
+/// - 排空超时时打印仍未完成、即将被中止的请求
+#[derive(Clone, Default)]
+pub(crate) struct Shutdown {
+    draining: Arc<AtomicBool>,
+    drain_started: Arc<Notify>,
+    next_id: Arc<AtomicU64>,
+    in_flight: Arc<Mutex<HashMap<u64, InFlightRequest>>>,
+}
+
+/// 请求结束（包括 future 被丢弃）时自动从登记表中移除
+pub(crate) struct InFlightGuard {
+    id: u64,
+    in_flight: Arc<Mutex<HashMap<u64, InFlightRequest>>>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(&self.id);
+        }
+    }
+}
+
+impl Shutdown {
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// 登记一个正在处理的请求
+    pub(crate) fn track(&self, request_id: String, path: String) -> InFlightGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.insert(
+                id,
+                InFlightRequest {
+                    request_id,
+                    path,
+                    started_at: Instant::now(),
+                },
+            );
+        }
+        InFlightGuard {
+            id,
+            in_flight: Arc::clone(&self.in_flight),
+        }
+    }
+
+    pub(crate) fn in_flight_count(&self) -> usize {
+        self.in_flight
+            .lock()
+            .map(|in_flight| in_flight.len())
+            .unwrap_or(0)
+    }
+
+    /// 进入排空阶段：`/ready` 开始返回未就绪
+    pub(crate) fn begin_drain(&self) {
+        if !self.draining.swap(true, Ordering::SeqCst) {
+            tracing::info!(
+                in_flight = self.in_flight_count(),
+                "收到停机信号，停止接受新连接并等待处理中的请求完成"
+            );
+            self.drain_started.notify_one();
+        }
+    }
+
+    /// ### 等待 SIGTERM 或 SIGINT
+    /// - 收到信号后进入排空阶段，用作 axum 的 graceful shutdown 信号
+    pub(crate) async fn wait_for_signal(self) {
+        let ctrl_c = async {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                tracing::error!(error = %e, "无法监听 SIGINT");
+                std::future::pending::<()>().await;
+            }
+        };
+
+        #[cfg(unix)]
+        let terminate = async {
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(mut signal) => {
+                    signal.recv().await;
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "无法监听 SIGTERM");
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => {},
+            _ = terminate => {},
+        }
+        self.begin_drain();
+    }
+
+    /// 排空开始后再等待 timeout，到期仍未结束的请求将被中止
+    pub(crate) async fn drain_deadline(&self, timeout: Duration) {
+        self.drain_started.notified().await;
+        tokio::time::sleep(timeout).await;
+    }
+
+    /// ### 记录排空超时时仍在处理的请求
+    /// #### 返回值
+    /// - 被中止的请求数
+    pub(crate) fn log_aborted(&self) -> usize {
+        let Ok(in_flight) = self.in_flight.lock() else {
+            return 0;
+        };
+        for request in in_flight.values() {
+            tracing::warn!(
+                request_id = %request.request_id,
+                path = %request.path,
+                elapsed_ms = request.started_at.elapsed().as_millis(),
+                "排空超时，请求被中止"
+            );
+        }
+        in_flight.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_tracks_in_flight_requests_until_deadline() {
+        let shutdown = Shutdown::default();
+        let finished = shutdown.track("a".to_string(), "/click/test".to_string());
+        let _pending = shutdown.track("b".to_string(), "/slide/simple_match".to_string());
+        assert_eq!(shutdown.in_flight_count(), 2);
+        drop(finished);
+        assert_eq!(shutdown.in_flight_count(), 1);
+
+        assert!(!shutdown.is_draining());
+        shutdown.begin_drain();
+        assert!(shutdown.is_draining());
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            shutdown.drain_deadline(Duration::from_millis(10)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(shutdown.log_aborted(), 1);
+    }
+}
@@ -1,26 +1,30 @@
 // slide.rs
 
-use crate::abstraction::{Api, GenerateW, Test, VerifyType};
+use crate::abstraction::{Api, GenerateW, ImageUrl, Test, VerifyOutcome, VerifyType};
+use crate::annotate;
 use crate::debug;
-use crate::error::{
-    missing_param, net_work_error, other, other_without_source, parse_error, Result,
-};
+use crate::entropy::Entropy;
+use crate::error::{missing_param, other, parse_error, Result};
+use crate::manual::Answer;
+use crate::recognizer::{self, SlideRecognizer};
+use crate::redact;
+use crate::restore::{restore_background, GEETEST_SLIDE};
+use crate::solve::{self, Keyed, Solve, SolveFlow};
+use crate::transport::{LiveTransport, Transport};
 use crate::w::slide_calculate;
-use captcha_breaker::captcha::Slide0;
-use image::{DynamicImage, GenericImage};
+use image::DynamicImage;
 use reqwest::blocking::Client;
-use serde_json::Value;
-use std::collections::HashMap;
 use std::sync::Arc;
-use std::thread::sleep;
-// 修改：引入 SystemTime 和 UNIX_EPOCH 用于生成时间戳
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::time::Instant;
 
 #[derive(Clone)]
 pub struct Slide {
     client: Arc<Client>,
     download_client: Arc<Client>,
+    transport: Arc<dyn Transport>,
+    entropy: Entropy,
     verify_type: VerifyType,
+    recognizer: Arc<dyn SlideRecognizer>,
 }
 
 impl Slide {
@@ -28,7 +32,10 @@ impl Slide {
         Slide {
             client,
             download_client,
+            transport: Arc::new(LiveTransport),
+            entropy: Entropy::default(),
             verify_type: VerifyType::Slide,
+            recognizer: recognizer::slide(),
         }
     }
 
@@ -37,43 +44,83 @@ impl Slide {
         self.download_client = new_download_client;
     }
 
-    // --- 新增函数 ---
-    pub fn simple_match(&mut self, gt: &str, challenge: &str) -> Result<(String, String)> {
-        // let (c, s) = self.get_c_s(gt, challenge, None)?;
-
-        // let verify_type = self.get_type(gt, challenge, None)?;
-        // if verify_type != VerifyType::Slide {
-        //     return Err(other_without_source("验证码类型错误"));
-        // }
+    /// 替换上游请求的发送方式，用于录制或回放
+    pub(crate) fn set_transport(&mut self, transport: Arc<dyn Transport>) {
+        self.transport = transport;
+    }
 
-        // let (_c, _s, args) = self.get_new_c_s_args(gt, challenge)?;
-        // // 注意滑块验证码这里要刷新challenge
-        // let challenge = args.0.clone();
-        // let key = self.calculate_key(args)?;
-        // let w = self.generate_w(&key, gt, &challenge, &c, &s)?;
-        // let (msg, validate) = self.verify(gt, &challenge, Some(&w))?;
-        // Ok((challenge, validate))
-        self.get_c_s(gt, challenge, None)?;
-        self.get_type(gt, challenge, None)?;
-        let (c, s, args) = self.get_new_c_s_args(gt, challenge)?;
-        let challenge = args.0.clone();
-        let start = Instant::now();
-        let key = self.calculate_key(args)?;
-        let w = self.generate_w(key.as_str(), gt, &challenge, c.as_ref(), s.as_str())?;
+    /// 替换随机数与时钟，固定后生成的 w 可以复现
+    pub(crate) fn set_entropy(&mut self, entropy: Entropy) {
+        self.entropy = entropy;
+    }
 
-        let elapsed = start.elapsed();
-        if elapsed < Duration::from_secs(2) {
-            let sleep_duration = Duration::from_secs(2) - elapsed;
-            sleep(sleep_duration);
+    /// ### 识别滑块缺口位置
+    /// - bg_img 为下载得到的乱序背景图，slice_img 为滑块图片
+    /// - 命令行和 HTTP 服务共用此逻辑，返回滑动距离
+    /// - 人工识别时返回入队的任务，收到答复后再算出滑动距离
+    pub(crate) fn recognize(
+        &self,
+        bg_img: &DynamicImage,
+        slice_img: DynamicImage,
+    ) -> Result<Keyed> {
+        let _span = tracing::info_span!("inference").entered();
+        let new_bg_img = restore_background(bg_img, &GEETEST_SLIDE)?;
+        debug::save_image("slide-background-restored", &new_bg_img);
+        let inference_started_at = Instant::now();
+        if let Some(parked) = self.recognizer.park(&new_bg_img, &slice_img)? {
+            let finish = move |answer: Answer| {
+                let res_x = answer.offset()?;
+                Ok(slide_key(
+                    &new_bg_img,
+                    &slice_img,
+                    res_x,
+                    inference_started_at,
+                ))
+            };
+            return Ok(Keyed::Parked(parked, Box::new(finish)));
         }
-        let (_, validate) = self.verify(gt, &challenge, Some(w.as_str()))?;
-        Ok((challenge, validate))
+        let res_x = self
+            .recognizer
+            .recognize(&new_bg_img, &slice_img)
+            .inspect_err(|e| {
+                tracing::debug!(
+                    error = %e,
+                    inference_ms = inference_started_at.elapsed().as_millis(),
+                    "滑块识别执行失败"
+                )
+            })?;
+        Ok(Keyed::Ready(slide_key(
+            &new_bg_img,
+            &slice_img,
+            res_x,
+            inference_started_at,
+        )))
     }
-    // --- 新增函数结束 ---
+}
+
+/// 把识别出的滑动距离作为 key，并记录调试产物
+fn slide_key(
+    new_bg_img: &DynamicImage,
+    slice_img: &DynamicImage,
+    res_x: u32,
+    inference_started_at: Instant,
+) -> String {
+    let key = res_x.to_string();
+    tracing::debug!(
+        offset_x = %redact::secret(&key),
+        inference_ms = inference_started_at.elapsed().as_millis(),
+        "滑块识别完成"
+    );
+    debug::save_rendered(debug::ANNOTATED, || {
+        annotate::slide(new_bg_img, slice_img, res_x)
+    });
+    debug::record_timing("inference", inference_started_at.elapsed());
+    debug::record_key(&key);
+    key
 }
 
 impl Api for Slide {
-    type ArgsType = (String, String, String, String);
+    type ArgsType = (String, ImageUrl, ImageUrl, ImageUrl);
 
     fn client(&self) -> &Client {
         &self.client
@@ -81,66 +128,38 @@ impl Api for Slide {
     fn download_client(&self) -> &Client {
         &self.download_client
     }
+    fn transport(&self) -> &dyn Transport {
+        self.transport.as_ref()
+    }
+    fn entropy(&self) -> &Entropy {
+        &self.entropy
+    }
 
     fn get_new_c_s_args(
         &self,
         gt: &str,
         challenge: &str,
     ) -> Result<(Vec<u8>, String, Self::ArgsType)> {
-        // 修改：生成动态回调
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis()
-            .to_string();
-        let callback = format!("geetest_{}", timestamp);
-
+        let _span = tracing::info_span!("get_new_c_s_args").entered();
         let url = "https://api.geetest.com/get.php";
-        let mut params = HashMap::from([
+        let params = [
             ("gt", gt),
             ("challenge", challenge),
             ("is_next", "true"),
             ("offline", "false"),
             ("isPC", "true"),
-            ("callback", callback.as_str()), // 使用动态回调
-        ]);
-        params.insert(
-            "type",
-            match self.verify_type {
-                VerifyType::Click => "click",
-                VerifyType::Slide => "slide",
-            },
-        );
-        let res = self
-            .client
-            .get(url)
-            .query(&params)
-            .send()
-            .map_err(net_work_error)?;
-        let res = res.text().map_err(|e| other("什么b玩意错误", e))?;
-
-        // 修改：使用动态回调作为前缀
-        let prefix = format!("{}(", callback);
-        let res = res
-            .strip_prefix(&prefix)
-            .ok_or_else(|| other_without_source("前缀错误"))?
-            .strip_suffix(")")
-            .ok_or_else(|| other_without_source("后缀错误"))?;
-
-        let res: Value = serde_json::from_str(res).map_err(parse_error)?;
+            (
+                "type",
+                match self.verify_type {
+                    VerifyType::Click => "click",
+                    VerifyType::Slide => "slide",
+                },
+            ),
+        ];
+        let res = self.jsonp(url, &params)?;
         let c: Vec<u8> =
             serde_json::from_value(res.get("c").ok_or_else(|| missing_param("c"))?.clone())
                 .map_err(parse_error)?;
-        let static_server = res
-            .get("static_servers")
-            .ok_or_else(|| missing_param("static_servers"))?
-            .as_array()
-            .ok_or_else(|| missing_param("static_servers"))?
-            .first()
-            .ok_or_else(|| other_without_source("static_servers里面咋没东西啊"))?
-            .as_str()
-            .ok_or_else(|| other_without_source("static_servers里面咋没东西啊"))?;
-
         Ok((
             c,
             // 改进：使用安全的错误处理替换 expect 和 unwrap
@@ -155,83 +174,22 @@ impl Api for Slide {
                     .as_str()
                     .ok_or_else(|| missing_param("challenge"))?
                     .to_string(),
-                format!(
-                    "https://{}{}",
-                    static_server,
-                    res.get("fullbg")
-                        .ok_or_else(|| missing_param("fullbg"))?
-                        .as_str()
-                        .ok_or_else(|| missing_param("fullbg"))?
-                ),
-                format!(
-                    "https://{}{}",
-                    static_server,
-                    res.get("bg")
-                        .ok_or_else(|| missing_param("bg"))?
-                        .as_str()
-                        .ok_or_else(|| missing_param("bg"))?
-                ),
-                format!(
-                    "https://{}{}",
-                    static_server,
-                    res.get("slice")
-                        .ok_or_else(|| missing_param("slice"))?
-                        .as_str()
-                        .ok_or_else(|| missing_param("slice"))?
-                ),
+                ImageUrl::from_response(&res, "static_servers", "fullbg")?,
+                ImageUrl::from_response(&res, "static_servers", "bg")?,
+                ImageUrl::from_response(&res, "static_servers", "slice")?,
             ),
         ))
     }
 
-    fn verify(&self, gt: &str, challenge: &str, w: Option<&str>) -> Result<(String, String)> {
-        // 修改：生成动态回调
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis()
-            .to_string();
-        let callback = format!("geetest_{}", timestamp);
-
+    fn verify(&self, gt: &str, challenge: &str, w: Option<&str>) -> Result<VerifyOutcome> {
+        let _span = tracing::info_span!("verify").entered();
         let url = "https://api.geetest.com/ajax.php";
-        let mut params = HashMap::from([
-            ("gt", gt),
-            ("challenge", challenge),
-            ("callback", callback.as_str()), // 使用动态回调
-        ]);
+        let mut params = vec![("gt", gt), ("challenge", challenge)];
         if let Some(w) = w {
-            params.insert("w", w);
+            params.push(("w", w));
         }
-        let res = self
-            .client()
-            .get(url)
-            .query(&params)
-            .send()
-            .map_err(net_work_error)?;
-        // 改进：使用安全的错误处理替换 unwrap
-        let res = res.text().map_err(|e| other("响应转文本失败", e))?;
-
-        // 修改：使用动态回调作为前缀
-        let prefix = format!("{}(", callback);
-        let res = res
-            .strip_prefix(&prefix)
-            .ok_or_else(|| other_without_source("前缀错误"))?
-            .strip_suffix(")")
-            .ok_or_else(|| other_without_source("后缀错误"))?;
-
-        // 改进：使用安全的错误处理替换 unwrap
-        let res: Value = serde_json::from_str(res).map_err(parse_error)?;
-        Ok((
-            res.get("message")
-                .ok_or_else(|| missing_param("message"))?
-                .as_str()
-                .ok_or_else(|| missing_param("message"))?
-                .to_string(),
-            res.get("validate")
-                .ok_or_else(|| missing_param("validate"))?
-                .as_str()
-                .ok_or_else(|| missing_param("validate"))?
-                .to_string(),
-        ))
+        let res = self.jsonp(url, &params)?;
+        Ok(VerifyOutcome::from_response(&res))
     }
 
     fn refresh(&self, _gt: &str, _challenge: &str) -> Result<Self::ArgsType> {
@@ -241,61 +199,7 @@ impl Api for Slide {
 
 impl GenerateW for Slide {
     fn calculate_key(&mut self, args: Self::ArgsType) -> Result<String> {
-        let started_at = Instant::now();
-        let (_, _, bg, slice) = args;
-        let bg_bytes = self.download_img(bg.as_str())?;
-        let slice_bytes = self.download_img(slice.as_str())?;
-        let slice_img = image::load_from_memory(&slice_bytes).map_err(|e| other("内部错误", e))?;
-        let bg_img = image::load_from_memory(&bg_bytes).map_err(|e| other("图片解析错误", e))?;
-        tracing::debug!(
-            background_bytes = bg_bytes.len(),
-            background_width = bg_img.width(),
-            background_height = bg_img.height(),
-            slice_bytes = slice_bytes.len(),
-            slice_width = slice_img.width(),
-            slice_height = slice_img.height(),
-            "滑块验证码图片已加载"
-        );
-        debug::save_image("slide-background-scrambled", &bg_img);
-        debug::save_image("slide-piece", &slice_img);
-        let mut new_bg_img = image::ImageBuffer::new(260, 160);
-        let offset = [
-            39, 38, 48, 49, 41, 40, 46, 47, 35, 34, 50, 51, 33, 32, 28, 29, 27, 26, 36, 37, 31, 30,
-            44, 45, 43, 42, 12, 13, 23, 22, 14, 15, 21, 20, 8, 9, 25, 24, 6, 7, 3, 2, 0, 1, 11, 10,
-            4, 5, 19, 18, 16, 17,
-        ];
-        let (w_sep, h_sep) = (10u32, 80u32);
-        for (idx, &off) in offset.iter().enumerate() {
-            let x = (off % 26 * 12) as u32;
-            let y = if off > 25 { h_sep } else { 0 };
-            let new_x = (idx % 26 * 10) as u32;
-            let new_y = if idx > 25 { h_sep } else { 0 };
-
-            let pi = bg_img.crop_imm(x, y, w_sep, h_sep);
-            new_bg_img
-                .copy_from(&pi, new_x, new_y)
-                .map_err(|e| other("重组滑块背景图失败", e))?;
-        }
-        let new_bg_img = DynamicImage::ImageRgba8(new_bg_img);
-        debug::save_image("slide-background-restored", &new_bg_img);
-        let inference_started_at = Instant::now();
-        let res_x = Slide0::run(&slice_img, &new_bg_img)
-            .map_err(|e| {
-                tracing::debug!(
-                    error = %e,
-                    inference_ms = inference_started_at.elapsed().as_millis(),
-                    "滑块识别执行失败"
-                );
-                other_without_source(&format!("滑块识别内部错误: {}", e))
-            })?
-            .x1;
-        tracing::debug!(
-            offset_x = res_x,
-            inference_ms = inference_started_at.elapsed().as_millis(),
-            total_ms = started_at.elapsed().as_millis(),
-            "滑块识别完成"
-        );
-        Ok(res_x.to_string())
+        self.begin_key(args)?.wait()
     }
 
     fn generate_w(
@@ -306,27 +210,59 @@ impl GenerateW for Slide {
         c: &[u8],
         s: &str,
     ) -> Result<String> {
+        let _span = tracing::info_span!("generate_w").entered();
         slide_calculate(
             key.parse().map_err(|e| other("滑动距离不是整数类型", e))?,
             gt,
             challenge,
             c,
             s,
+            &self.entropy,
         )
     }
 }
 
 impl Test for Slide {
     fn test(&mut self, url: &str) -> Result<String> {
-        let (gt, mut challenge) = self.register_test(url)?;
-        let (c, s) = self.get_c_s(gt.as_str(), challenge.as_str(), None)?;
-        self.get_type(gt.as_str(), challenge.as_str(), None)?;
-        let (_c, _s, args) = self.get_new_c_s_args(gt.as_str(), challenge.as_str())?;
-        challenge = args.0.clone();
-        let key = self.calculate_key(args)?;
-        // 改进：使用 generate_w 方法以保持一致性，并进行错误处理
-        let w = self.generate_w(key.as_str(), &gt, &challenge, &c, &s)?;
-        let (_, validate) = self.verify(gt.as_str(), challenge.as_str(), Some(w.as_str()))?;
-        Ok(validate)
+        let result = solve::run_to_end(SolveFlow::register(self.clone(), url))?;
+        Ok(result.validate)
+    }
+}
+
+impl Solve for Slide {
+    const TYPE: VerifyType = VerifyType::Slide;
+
+    /// 注意滑块验证码获取图片时会刷新 challenge
+    fn submit_challenge(_challenge: &str, args: &Self::ArgsType) -> String {
+        args.0.clone()
+    }
+
+    fn begin_key(&mut self, args: Self::ArgsType) -> Result<Keyed> {
+        let started_at = Instant::now();
+        let (_, _, bg, slice) = args;
+        let bg_bytes = self.download_img(&bg)?;
+        let slice_bytes = self.download_img(&slice)?;
+        debug::record_timing("download", started_at.elapsed());
+        let slice_img = image::load_from_memory(&slice_bytes).map_err(|e| other("内部错误", e))?;
+        let bg_img = image::load_from_memory(&bg_bytes).map_err(|e| other("图片解析错误", e))?;
+        tracing::debug!(
+            background_bytes = bg_bytes.len(),
+            background_width = bg_img.width(),
+            background_height = bg_img.height(),
+            slice_bytes = slice_bytes.len(),
+            slice_width = slice_img.width(),
+            slice_height = slice_img.height(),
+            "滑块验证码图片已加载"
+        );
+        debug::save_image("slide-background-scrambled", &bg_img);
+        debug::save_image("slide-piece", &slice_img);
+        let keyed = self.recognize(&bg_img, slice_img)?;
+        if let Keyed::Ready(_) = keyed {
+            tracing::debug!(
+                total_ms = started_at.elapsed().as_millis(),
+                "滑块 key 计算完成"
+            );
+        }
+        Ok(keyed)
     }
 }
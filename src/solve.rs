@@ -0,0 +1,456 @@
+// solve.rs
+
+use crate::abstraction::{GenerateW, SolveResult, SolveTimings, VerifyOutcome, VerifyType};
+use crate::error::{other_without_source, verify_rejected, Result};
+use crate::manual::{self, Answer, Parked};
+use std::time::{Duration, Instant};
+
+/// 极验要求从获取图片到提交验证至少间隔的时间，提交过快会被判定为异常
+pub(crate) const SUBMIT_DELAY: Duration = Duration::from_secs(2);
+/// 识别失败后刷新图片前的间隔
+const REFRESH_DELAY: Duration = Duration::from_millis(250);
+
+/// 流程执行一段之后的状态
+pub(crate) enum Step<T> {
+    /// 需要等到指定时间再继续；服务端用异步定时器等待，不占用阻塞线程
+    Wait(Instant),
+    /// 等待人工识别的答复；服务端异步等待，收到答复或超时后调用 `Flow::resume`
+    Parked(Parked),
+    Done(T),
+}
+
+/// ### 分段执行的流程
+/// - 每次调用 advance 执行一段阻塞操作，需要等待时返回 `Step::Wait`，由调用方决定如何等待
+pub(crate) trait Flow {
+    type Output;
+    fn advance(&mut self) -> Result<Step<Self::Output>>;
+
+    /// 交回 `Step::Parked` 的答复，超时为 None；只有会返回 `Step::Parked` 的流程需要实现
+    fn resume(&mut self, _answer: Option<Answer>) {}
+}
+
+/// 在当前线程中把流程执行完，供命令行等不需要并发的场景使用
+pub(crate) fn run_to_end<F: Flow>(mut flow: F) -> Result<F::Output> {
+    loop {
+        match flow.advance()? {
+            Step::Wait(until) => {
+                std::thread::sleep(until.saturating_duration_since(Instant::now()))
+            }
+            Step::Parked(parked) => flow.resume(parked.blocking_answer()),
+            Step::Done(output) => return Ok(output),
+        }
+    }
+}
+
+/// 收到人工答复后算出 key
+pub(crate) type Finish = Box<dyn FnOnce(Answer) -> Result<String> + Send>;
+
+/// ### 识别得到的 key
+/// - 人工识别时为已入队的任务和收到答复后算出 key 的函数
+pub(crate) enum Keyed {
+    Ready(String),
+    Parked(Parked, Finish),
+}
+
+impl Keyed {
+    /// 在当前线程等到 key，供命令行等不需要并发的场景使用
+    pub(crate) fn wait(self) -> Result<String> {
+        match self {
+            Keyed::Ready(key) => Ok(key),
+            Keyed::Parked(parked, finish) => {
+                finish(parked.blocking_answer().ok_or_else(manual::timed_out)?)
+            }
+        }
+    }
+}
+
+/// ### 可以走完整识别流程的验证码类型
+/// - 流程会在线程间移动，图片参数须可跨线程传递
+pub(crate) trait Solve: GenerateW<ArgsType: Send> + Send + 'static {
+    const TYPE: VerifyType;
+
+    /// 获取图片参数后提交验证使用的 challenge，滑块验证码会在这里换新
+    fn submit_challenge(challenge: &str, _args: &Self::ArgsType) -> String {
+        challenge.to_string()
+    }
+
+    /// 下载图片并识别，人工识别时不等待答复，默认直接调用 calculate_key
+    fn begin_key(&mut self, args: Self::ArgsType) -> Result<Keyed> {
+        self.calculate_key(args).map(Keyed::Ready)
+    }
+}
+
+enum Stage<A> {
+    Register(String),
+    Detect,
+    Fetch,
+    Recognize(A),
+    /// 等待人工识别，answer 由 resume 填入
+    Parked {
+        finish: Finish,
+        started_at: Instant,
+        answer: Option<Answer>,
+    },
+    Submit {
+        key: String,
+        w: String,
+    },
+    Refresh,
+    Finished,
+}
+
+/// ### 一次完整的识别流程
+/// - 注册（仅 test）→ 获取 c/s 和类型 → 获取图片参数 → 识别并生成 w → 等待 → 提交验证
+/// - 开启重试时，识别错误或网络错误会刷新图片重来，直到用完次数
+pub(crate) struct SolveFlow<T: Solve> {
+    solver: T,
+    gt: String,
+    challenge: String,
+    max_attempts: u32,
+    attempts: u32,
+    started_at: Instant,
+    timings: SolveTimings,
+    c: Vec<u8>,
+    s: String,
+    stage: Stage<T::ArgsType>,
+}
+
+impl<T: Solve> SolveFlow<T> {
+    pub(crate) fn new(solver: T, gt: &str, challenge: &str) -> Self {
+        Self {
+            solver,
+            gt: gt.to_string(),
+            challenge: challenge.to_string(),
+            max_attempts: 1,
+            attempts: 0,
+            started_at: Instant::now(),
+            timings: SolveTimings::default(),
+            c: Vec::new(),
+            s: String::new(),
+            stage: Stage::Detect,
+        }
+    }
+
+    /// 先用注册地址申请 gt 和 challenge
+    pub(crate) fn register(solver: T, url: &str) -> Self {
+        Self {
+            stage: Stage::Register(url.to_string()),
+            ..Self::new(solver, "", "")
+        }
+    }
+
+    /// 最多提交 max_attempts 次验证
+    pub(crate) fn retry(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// 调用方已经调用过 get_c_s 和 get_type，started_at 为整个识别的开始时间
+    pub(crate) fn detected(mut self, started_at: Instant) -> Self {
+        self.started_at = started_at;
+        self.stage = Stage::Fetch;
+        self
+    }
+
+    /// 还有剩余次数时等待后刷新图片重试，否则返回错误
+    fn retry_or_fail(&mut self, error: crate::error::Error) -> Result<Step<SolveResult>> {
+        if self.attempts >= self.max_attempts {
+            return Err(error);
+        }
+        tracing::warn!(attempt = self.attempts, error = %error, "验证未通过，刷新图片后重试");
+        self.stage = Stage::Refresh;
+        Ok(Step::Wait(Instant::now() + REFRESH_DELAY))
+    }
+
+    fn recognize(&mut self, args: T::ArgsType) -> Result<Step<SolveResult>> {
+        self.attempts += 1;
+        let started_at = Instant::now();
+        match self.solver.begin_key(args)? {
+            Keyed::Ready(key) => self.generate(key, started_at),
+            Keyed::Parked(parked, finish) => {
+                self.stage = Stage::Parked {
+                    finish,
+                    started_at,
+                    answer: None,
+                };
+                Ok(Step::Parked(parked))
+            }
+        }
+    }
+
+    /// 识别得到 key 后生成 w，等到提交时间后再提交
+    fn generate(&mut self, key: String, started_at: Instant) -> Result<Step<SolveResult>> {
+        SolveTimings::add(&mut self.timings.recognize_ms, started_at);
+        let generate_started_at = Instant::now();
+        let w = self
+            .solver
+            .generate_w(&key, &self.gt, &self.challenge, &self.c, &self.s)?;
+        SolveTimings::add(&mut self.timings.generate_w_ms, generate_started_at);
+
+        let not_before = started_at + SUBMIT_DELAY;
+        self.timings.wait_ms += not_before
+            .saturating_duration_since(Instant::now())
+            .as_millis() as u64;
+        self.stage = Stage::Submit { key, w };
+        Ok(Step::Wait(not_before))
+    }
+
+    fn submit(&mut self, key: String, w: String) -> Result<Step<SolveResult>> {
+        let started_at = Instant::now();
+        let outcome = self.solver.verify(&self.gt, &self.challenge, Some(&w));
+        SolveTimings::add(&mut self.timings.verify_ms, started_at);
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(error) => return self.retry_or_fail(error),
+        };
+        match outcome {
+            VerifyOutcome::Success { validate, seccode } => {
+                SolveTimings::add(&mut self.timings.total_ms, self.started_at);
+                Ok(Step::Done(SolveResult {
+                    captcha_type: T::TYPE,
+                    challenge: std::mem::take(&mut self.challenge),
+                    validate,
+                    seccode,
+                    attempts: self.attempts,
+                    key,
+                    timings: std::mem::take(&mut self.timings),
+                }))
+            }
+            // 只有识别错误值得刷新图片重试，其余结果重试同一 challenge 也不会通过
+            VerifyOutcome::Fail => self.retry_or_fail(verify_rejected(VerifyOutcome::Fail)),
+            outcome => Err(verify_rejected(outcome)),
+        }
+    }
+}
+
+impl<T: Solve> Flow for SolveFlow<T> {
+    type Output = SolveResult;
+
+    fn advance(&mut self) -> Result<Step<SolveResult>> {
+        loop {
+            match std::mem::replace(&mut self.stage, Stage::Finished) {
+                Stage::Register(url) => {
+                    let (gt, challenge) = self.solver.register_test(&url)?;
+                    self.gt = gt;
+                    self.challenge = challenge;
+                    self.started_at = Instant::now();
+                    self.stage = Stage::Detect;
+                }
+                Stage::Detect => {
+                    self.solver.get_c_s(&self.gt, &self.challenge, None)?;
+                    self.solver.get_type(&self.gt, &self.challenge, None)?;
+                    self.stage = Stage::Fetch;
+                }
+                Stage::Fetch => {
+                    let (c, s, args) = self.solver.get_new_c_s_args(&self.gt, &self.challenge)?;
+                    self.challenge = T::submit_challenge(&self.challenge, &args);
+                    self.c = c;
+                    self.s = s;
+                    SolveTimings::add(&mut self.timings.prepare_ms, self.started_at);
+                    self.stage = Stage::Recognize(args);
+                }
+                Stage::Recognize(args) => {
+                    return match self.recognize(args) {
+                        Err(error) => self.retry_or_fail(error),
+                        step => step,
+                    };
+                }
+                Stage::Parked {
+                    finish,
+                    started_at,
+                    answer,
+                } => {
+                    let key = answer.ok_or_else(manual::timed_out).and_then(finish);
+                    return match key.and_then(|key| self.generate(key, started_at)) {
+                        Err(error) => self.retry_or_fail(error),
+                        step => step,
+                    };
+                }
+                Stage::Submit { key, w } => return self.submit(key, w),
+                Stage::Refresh => {
+                    let started_at = Instant::now();
+                    let args = self.solver.refresh(&self.gt, &self.challenge)?;
+                    SolveTimings::add(&mut self.timings.prepare_ms, started_at);
+                    self.stage = Stage::Recognize(args);
+                }
+                Stage::Finished => return Err(other_without_source("识别流程已结束")),
+            }
+        }
+    }
+
+    fn resume(&mut self, answer: Option<Answer>) {
+        if let Stage::Parked { answer: slot, .. } = &mut self.stage {
+            *slot = answer;
+        }
+    }
+}
+
+/// 测试用的假验证码实例
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::{Keyed, Solve};
+    use crate::abstraction::{Api, GenerateW, VerifyOutcome, VerifyType};
+    use crate::entropy::Entropy;
+    use crate::error::Result;
+    use crate::manual::{Answer, ManualRecognizer};
+    use crate::recognizer::ClickRecognizer;
+    use crate::transport::{LiveTransport, Transport};
+    use image::DynamicImage;
+    use reqwest::blocking::Client;
+    use std::sync::{Arc, Mutex};
+
+    /// ### 按预设结果应答的假实例
+    /// - SLIDE 决定 `Solve::TYPE`，get_type 总是返回滑块
+    /// - 图片参数为刷新次数，首次为 0，刷新后为 1
+    /// - verify 依次返回预设结果，用完后返回成功
+    /// - 每次调用以 `click.verify` 的形式记录在共享列表中
+    /// - 设置人工识别后识别任务入队，答复的坐标作为 key
+    #[derive(Clone)]
+    pub(crate) struct Scripted<const SLIDE: bool> {
+        client: Client,
+        entropy: Entropy,
+        calls: Arc<Mutex<Vec<String>>>,
+        outcomes: Arc<Mutex<Vec<VerifyOutcome>>>,
+        manual: Option<Arc<ManualRecognizer>>,
+    }
+
+    impl<const SLIDE: bool> Scripted<SLIDE> {
+        pub(crate) fn new(calls: &Arc<Mutex<Vec<String>>>) -> Self {
+            Self {
+                client: Client::new(),
+                entropy: Entropy::default(),
+                calls: Arc::clone(calls),
+                outcomes: Arc::default(),
+                manual: None,
+            }
+        }
+
+        pub(crate) fn with_manual(mut self, manual: &Arc<ManualRecognizer>) -> Self {
+            self.manual = Some(Arc::clone(manual));
+            self
+        }
+
+        pub(crate) fn with_outcomes(self, outcomes: Vec<VerifyOutcome>) -> Self {
+            *self.outcomes.lock().unwrap() = outcomes;
+            self
+        }
+
+        fn record(&self, call: &str) {
+            let name = if SLIDE { "slide" } else { "click" };
+            self.calls.lock().unwrap().push(format!("{name}.{call}"));
+        }
+    }
+
+    impl<const SLIDE: bool> Api for Scripted<SLIDE> {
+        type ArgsType = u32;
+
+        fn get_c_s(&self, _: &str, _: &str, _: Option<&str>) -> Result<(Vec<u8>, String)> {
+            self.record("get_c_s");
+            Ok((vec![1, 2, 3], "s".to_string()))
+        }
+        fn get_type(&self, _: &str, _: &str, _: Option<&str>) -> Result<VerifyType> {
+            self.record("get_type");
+            Ok(VerifyType::Slide)
+        }
+        fn get_new_c_s_args(&self, _: &str, _: &str) -> Result<(Vec<u8>, String, u32)> {
+            self.record("get_new_c_s_args");
+            Ok((vec![1, 2, 3], "s".to_string(), 0))
+        }
+        fn verify(&self, _: &str, _: &str, _: Option<&str>) -> Result<VerifyOutcome> {
+            self.record("verify");
+            let mut outcomes = self.outcomes.lock().unwrap();
+            if outcomes.is_empty() {
+                return Ok(VerifyOutcome::Success {
+                    validate: "v".to_string(),
+                    seccode: "v|jordan".to_string(),
+                });
+            }
+            Ok(outcomes.remove(0))
+        }
+        fn refresh(&self, _: &str, _: &str) -> Result<u32> {
+            self.record("refresh");
+            Ok(1)
+        }
+        fn client(&self) -> &Client {
+            &self.client
+        }
+        fn download_client(&self) -> &Client {
+            &self.client
+        }
+        fn transport(&self) -> &dyn Transport {
+            &LiveTransport
+        }
+        fn entropy(&self) -> &Entropy {
+            &self.entropy
+        }
+    }
+
+    impl<const SLIDE: bool> GenerateW for Scripted<SLIDE> {
+        fn calculate_key(&mut self, args: u32) -> Result<String> {
+            Ok(format!("key-{args}"))
+        }
+        fn generate_w(&self, _: &str, _: &str, _: &str, _: &[u8], _: &str) -> Result<String> {
+            Ok("w".to_string())
+        }
+    }
+
+    impl<const SLIDE: bool> Solve for Scripted<SLIDE> {
+        const TYPE: VerifyType = if SLIDE {
+            VerifyType::Slide
+        } else {
+            VerifyType::Click
+        };
+
+        fn begin_key(&mut self, args: u32) -> Result<Keyed> {
+            let Some(manual) = &self.manual else {
+                return self.calculate_key(args).map(Keyed::Ready);
+            };
+            let picture = DynamicImage::new_rgb8(344, 384);
+            let parked =
+                ClickRecognizer::park(manual.as_ref(), &picture)?.expect("人工识别总会入队");
+            let finish = |answer: Answer| Ok(format!("{:?}", answer.points()?));
+            Ok(Keyed::Parked(parked, Box::new(finish)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::Scripted;
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn flow_returns_waits_instead_of_sleeping_and_retries_on_fail() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let solver = Scripted::<false>::new(&calls).with_outcomes(vec![VerifyOutcome::Fail]);
+        let started_at = Instant::now();
+        let mut flow = SolveFlow::new(solver, "gt", "challenge")
+            .detected(started_at)
+            .retry(5);
+
+        let Ok(Step::Wait(submit_at)) = flow.advance() else {
+            panic!("生成 w 后应等待提交");
+        };
+        assert!(submit_at >= started_at + SUBMIT_DELAY);
+        // 第一次提交识别错误，等待后刷新图片，再次等待提交
+        assert!(matches!(flow.advance(), Ok(Step::Wait(_))));
+        assert!(matches!(flow.advance(), Ok(Step::Wait(_))));
+        let Ok(Step::Done(result)) = flow.advance() else {
+            panic!("第二次提交应通过");
+        };
+        assert_eq!(result.attempts, 2);
+        assert_eq!(result.key, "key-1");
+        assert_eq!(result.seccode, "v|jordan");
+        assert!(started_at.elapsed() < SUBMIT_DELAY, "流程本身不应阻塞等待");
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "click.get_new_c_s_args",
+                "click.verify",
+                "click.refresh",
+                "click.verify"
+            ]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+// telemetry.rs
+
+use crate::error::{other, Result};
+use clap::ValueEnum;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{
+    layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+
+const SERVICE_NAME: &str = "bili_ticket_gt_server";
+
+/// 日志输出格式
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub(crate) enum LogFormat {
+    /// 便于人工阅读的文本格式
+    #[default]
+    Text,
+    /// 每行一个 JSON 对象，便于日志采集
+    Json,
+}
+
+/// 持有 OTLP 导出器，进程退出前需要调用 `shutdown` 把缓冲中的 span 发送出去
+pub(crate) struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    pub(crate) fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("OTLP 导出器关闭失败: {e}");
+            }
+        }
+    }
+}
+
+/// ### 构建 OTLP span 导出器
+/// - endpoint 为采集器地址，例如 `http://localhost:4318`，未以 `/v1/traces` 结尾时自动补上
+pub(crate) fn otlp_provider(endpoint: &str) -> Result<SdkTracerProvider> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let endpoint = if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{endpoint}/v1/traces")
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| other("OTLP 导出器构建失败", e))?;
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build())
+}
+
+/// ### 初始化全局日志与链路追踪
+/// - format 决定日志输出为文本还是 JSON
+/// - 指定 otlp_endpoint 时，HTTP 请求和识别各阶段的 span 会通过 OTLP/HTTP 导出
+pub(crate) fn init(
+    filter: EnvFilter,
+    writer: BoxMakeWriter,
+    format: LogFormat,
+    otlp_endpoint: Option<&str>,
+) -> Result<Telemetry> {
+    let fmt_layer: Box<dyn Layer<Registry> + Send + Sync> = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    let provider = otlp_endpoint.map(otlp_provider).transpose()?;
+    let otel_layer = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(otel_layer)
+        .with(filter)
+        .init();
+
+    Ok(Telemetry { provider })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn spans_are_exported_to_otlp_collector() {
+        // 只接收一次请求的假采集器，把请求行回传给测试线程
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = vec![0; 64 * 1024];
+            let n = stream.read(&mut buf).unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            tx.send(request).unwrap();
+        });
+
+        let provider = otlp_provider(&endpoint).unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("inference").entered();
+        });
+        provider.shutdown().unwrap();
+
+        let request = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(request.starts_with("POST /v1/traces "));
+    }
+}
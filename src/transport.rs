@@ -0,0 +1,518 @@
+// transport.rs
+
+use crate::error::{
+    other, other_without_source, parse_error, upstream_error, upstream_network_error, Result,
+};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 验证码图片默认大小上限
+pub(crate) const DEFAULT_MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+/// JSON/JSONP 接口的响应都很小，固定上限即可
+pub(crate) const MAX_JSON_BYTES: u64 = 1024 * 1024;
+
+/// 单个图片服务器的默认超时，超时后换下一个服务器
+pub(crate) const DEFAULT_IMAGE_HOST_TIMEOUT: Duration = Duration::from_secs(5);
+
+static MAX_IMAGE_BYTES: AtomicU64 = AtomicU64::new(DEFAULT_MAX_IMAGE_BYTES);
+static IMAGE_HOST_TIMEOUT_MS: AtomicU64 =
+    AtomicU64::new(DEFAULT_IMAGE_HOST_TIMEOUT.as_millis() as u64);
+
+pub(crate) fn set_max_image_bytes(max_bytes: u64) {
+    MAX_IMAGE_BYTES.store(max_bytes, Ordering::Relaxed);
+}
+
+pub(crate) fn set_image_host_timeout(timeout: Duration) {
+    IMAGE_HOST_TIMEOUT_MS.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// ### 单次请求的限制
+/// - max_bytes: 响应体超过该大小时不再继续读取，直接返回错误
+/// - timeout: 覆盖客户端默认超时，为空时使用客户端配置
+#[derive(Clone, Copy)]
+pub(crate) struct Limits {
+    pub(crate) max_bytes: u64,
+    pub(crate) timeout: Option<Duration>,
+}
+
+/// 上游接口的一次响应
+pub(crate) struct UpstreamResponse {
+    pub(crate) status: u16,
+    pub(crate) content_type: Option<String>,
+    pub(crate) body: Vec<u8>,
+}
+
+/// ### 上游请求的发送方式
+/// - `Click`/`Slide` 的所有上游请求（JSONP 接口和图片下载）都经过这里
+/// - 默认直接发起网络请求，也可以录制到夹具目录或从夹具目录回放
+pub(crate) trait Transport: Send + Sync {
+    fn get(
+        &self,
+        client: &Client,
+        url: &str,
+        query: &[(&str, &str)],
+        limits: Limits,
+    ) -> Result<UpstreamResponse>;
+}
+
+/// 上游响应应有的类型
+#[derive(Clone, Copy)]
+pub(crate) enum Expected {
+    /// 注册接口返回的 JSON
+    Json,
+    /// 极验的 JSONP 接口
+    Jsonp,
+    /// 验证码图片
+    Image,
+}
+
+impl Expected {
+    fn limits(self) -> Limits {
+        match self {
+            Expected::Json | Expected::Jsonp => Limits {
+                max_bytes: MAX_JSON_BYTES,
+                timeout: None,
+            },
+            Expected::Image => Limits {
+                max_bytes: MAX_IMAGE_BYTES.load(Ordering::Relaxed),
+                timeout: Some(Duration::from_millis(
+                    IMAGE_HOST_TIMEOUT_MS.load(Ordering::Relaxed),
+                )),
+            },
+        }
+    }
+
+    fn accepts(self, content_type: Option<&str>, body: &[u8]) -> bool {
+        match self {
+            // 部分注册接口不带 Content-Type，交给后续的 JSON 解析判断
+            Expected::Json => content_type.is_none_or(|ct| ct.contains("json")),
+            Expected::Jsonp => content_type.is_none_or(|ct| {
+                ct.contains("javascript") || ct.contains("json") || ct.starts_with("text/plain")
+            }),
+            // 图片服务器偶尔返回 application/octet-stream，此时按文件头判断
+            Expected::Image => {
+                content_type.is_some_and(|ct| ct.starts_with("image/"))
+                    || image::guess_format(body).is_ok()
+            }
+        }
+    }
+}
+
+/// ### 请求上游并检查响应
+/// - 状态码必须为 2xx，响应类型须与 expected 相符，响应体不能超过大小上限
+/// - 失败时的错误带有状态码、主机和请求路径，便于区分图片服务器故障和识别失败
+pub(crate) fn fetch(
+    transport: &dyn Transport,
+    client: &Client,
+    url: &str,
+    query: &[(&str, &str)],
+    expected: Expected,
+) -> Result<UpstreamResponse> {
+    fetch_with_limits(transport, client, url, query, expected, expected.limits())
+}
+
+/// 按给定的限制请求上游，`fetch` 使用 expected 对应的全局配置
+fn fetch_with_limits(
+    transport: &dyn Transport,
+    client: &Client,
+    url: &str,
+    query: &[(&str, &str)],
+    expected: Expected,
+    limits: Limits,
+) -> Result<UpstreamResponse> {
+    let max_bytes = limits.max_bytes;
+    let res = transport.get(client, url, query, limits)?;
+    if !(200..300).contains(&res.status) {
+        return Err(upstream_error(Some(res.status), url, "HTTP 状态码异常"));
+    }
+    if res.body.len() as u64 > max_bytes {
+        return Err(upstream_error(
+            Some(res.status),
+            url,
+            &format!("响应体超过 {max_bytes} 字节上限"),
+        ));
+    }
+    if !expected.accepts(res.content_type.as_deref(), &res.body) {
+        return Err(upstream_error(
+            Some(res.status),
+            url,
+            &format!(
+                "响应类型不符: {}",
+                res.content_type.as_deref().unwrap_or("<无 Content-Type>")
+            ),
+        ));
+    }
+    Ok(res)
+}
+
+/// 直接使用 reqwest 客户端请求上游
+pub(crate) struct LiveTransport;
+
+impl Transport for LiveTransport {
+    fn get(
+        &self,
+        client: &Client,
+        url: &str,
+        query: &[(&str, &str)],
+        limits: Limits,
+    ) -> Result<UpstreamResponse> {
+        let mut request = client.get(url).query(query);
+        if let Some(timeout) = limits.timeout {
+            request = request.timeout(timeout);
+        }
+        let res = request.send().map_err(|e| upstream_network_error(url, e))?;
+        let status = res.status().as_u16();
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = read_limited(res, url, limits.max_bytes)?;
+        Ok(UpstreamResponse {
+            status,
+            content_type,
+            body,
+        })
+    }
+}
+
+/// ### 读取响应体，超过 max_bytes 时返回上游错误
+/// - Content-Length 超限时不读取响应体
+/// - Content-Length 可能缺失或不实，读取时同样限制长度
+pub(crate) fn read_limited(res: Response, url: &str, max_bytes: u64) -> Result<Vec<u8>> {
+    let status = res.status().as_u16();
+    let too_large = || {
+        upstream_error(
+            Some(status),
+            url,
+            &format!("响应体超过 {max_bytes} 字节上限"),
+        )
+    };
+    if res
+        .content_length()
+        .is_some_and(|length| length > max_bytes)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    res.take(max_bytes + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| upstream_network_error(url, e))?;
+    if body.len() as u64 > max_bytes {
+        return Err(too_large());
+    }
+    Ok(body)
+}
+
+/// ### 夹具中的一次请求记录
+/// - 每次请求对应 `NNN.json`，响应体单独保存为 `NNN.<扩展名>`
+#[derive(Serialize, Deserialize)]
+struct Exchange {
+    url: String,
+    query: Vec<(String, String)>,
+    status: u16,
+    content_type: Option<String>,
+    /// 响应体文件名，请求失败时为空
+    body_file: Option<String>,
+    /// 请求失败时的错误信息
+    error: Option<String>,
+}
+
+/// 把经过的每一次请求和响应写入夹具目录，再原样返回给调用方
+pub(crate) struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    dir: PathBuf,
+    sequence: AtomicUsize,
+}
+
+impl RecordingTransport {
+    pub(crate) fn new(inner: Arc<dyn Transport>, dir: PathBuf) -> Self {
+        Self {
+            inner,
+            dir,
+            sequence: AtomicUsize::new(0),
+        }
+    }
+
+    fn write(&self, sequence: usize, exchange: &Exchange, body: Option<&[u8]>) -> Result<()> {
+        fs::create_dir_all(&self.dir).map_err(|e| other("无法创建录制目录", e))?;
+        if let (Some(file), Some(body)) = (&exchange.body_file, body) {
+            fs::write(self.dir.join(file), body).map_err(|e| other("写入录制响应体失败", e))?;
+        }
+        let meta = serde_json::to_vec_pretty(exchange).map_err(parse_error)?;
+        fs::write(self.dir.join(format!("{sequence:03}.json")), meta)
+            .map_err(|e| other("写入录制记录失败", e))
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn get(
+        &self,
+        client: &Client,
+        url: &str,
+        query: &[(&str, &str)],
+        limits: Limits,
+    ) -> Result<UpstreamResponse> {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let result = self.inner.get(client, url, query, limits);
+        let mut exchange = Exchange {
+            url: url.to_string(),
+            query: query
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            status: 0,
+            content_type: None,
+            body_file: None,
+            error: None,
+        };
+        let body = match &result {
+            Ok(res) => {
+                exchange.status = res.status;
+                exchange.content_type = res.content_type.clone();
+                exchange.body_file = Some(format!(
+                    "{sequence:03}.{}",
+                    extension_for(res.content_type.as_deref())
+                ));
+                Some(res.body.as_slice())
+            }
+            Err(e) => {
+                exchange.error = Some(e.to_string());
+                None
+            }
+        };
+        // 录制失败不应影响本次识别，只记录日志
+        if let Err(e) = self.write(sequence, &exchange, body) {
+            tracing::warn!(path = %self.dir.display(), error = %e, "录制上游请求失败");
+        }
+        result
+    }
+}
+
+fn extension_for(content_type: Option<&str>) -> &'static str {
+    let content_type = content_type.unwrap_or_default();
+    if content_type.starts_with("image/png") {
+        "png"
+    } else if content_type.starts_with("image/jpeg") {
+        "jpg"
+    } else if content_type.starts_with("image/webp") {
+        "webp"
+    } else if content_type.starts_with("text/")
+        || content_type.contains("javascript")
+        || content_type.contains("json")
+    {
+        "txt"
+    } else {
+        "bin"
+    }
+}
+
+/// ### 按录制顺序回放夹具目录中的响应
+/// - 请求地址必须与录制时一致，JSONP 回调名会替换成本次请求的回调名
+pub(crate) struct ReplayTransport {
+    dir: PathBuf,
+    exchanges: Mutex<VecDeque<Exchange>>,
+}
+
+impl ReplayTransport {
+    pub(crate) fn load(dir: &Path) -> Result<Self> {
+        let mut files = fs::read_dir(dir)
+            .map_err(|e| other("无法读取回放目录", e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect::<Vec<_>>();
+        files.sort();
+        let mut exchanges = VecDeque::with_capacity(files.len());
+        for file in files {
+            let content = fs::read(&file).map_err(|e| other("无法读取回放记录", e))?;
+            exchanges.push_back(serde_json::from_slice(&content).map_err(parse_error)?);
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn get(
+        &self,
+        _client: &Client,
+        url: &str,
+        query: &[(&str, &str)],
+        _limits: Limits,
+    ) -> Result<UpstreamResponse> {
+        let exchange = self
+            .exchanges
+            .lock()
+            .map_err(|_| other_without_source("回放队列 mutex poisoned"))?
+            .pop_front()
+            .ok_or_else(|| other_without_source(&format!("回放记录已用完: {url}")))?;
+        if exchange.url != url {
+            return Err(other_without_source(&format!(
+                "回放请求与录制不一致: 录制为 {}，实际为 {url}",
+                exchange.url
+            )));
+        }
+        if let Some(error) = exchange.error {
+            return Err(other_without_source(&format!(
+                "回放录制时的请求错误: {error}"
+            )));
+        }
+        let file = exchange
+            .body_file
+            .ok_or_else(|| other_without_source("回放记录缺少响应体"))?;
+        let mut body = fs::read(self.dir.join(file)).map_err(|e| other("无法读取回放响应体", e))?;
+
+        let recorded_callback = exchange
+            .query
+            .iter()
+            .find(|(k, _)| k == "callback")
+            .map(|(_, v)| v.as_str());
+        let callback = query
+            .iter()
+            .find(|(k, _)| *k == "callback")
+            .map(|(_, v)| *v);
+        if let (Some(recorded), Some(callback)) = (recorded_callback, callback) {
+            if let Some(rest) = body.strip_prefix(format!("{recorded}(").as_bytes()) {
+                body = [format!("{callback}(").as_bytes(), rest].concat();
+            }
+        }
+
+        Ok(UpstreamResponse {
+            status: exchange.status,
+            content_type: exchange.content_type,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 固定返回同一个 JSONP 响应的假上游
+    struct StubTransport;
+
+    impl Transport for StubTransport {
+        fn get(
+            &self,
+            _client: &Client,
+            _url: &str,
+            _query: &[(&str, &str)],
+            _limits: Limits,
+        ) -> Result<UpstreamResponse> {
+            Ok(UpstreamResponse {
+                status: 200,
+                content_type: Some("text/javascript;charset=UTF-8".to_string()),
+                body: br#"geetest_1({"status": "success"})"#.to_vec(),
+            })
+        }
+    }
+
+    /// 返回指定响应的假图片服务器
+    struct CannedTransport(u16, &'static str, Vec<u8>);
+
+    impl Transport for CannedTransport {
+        fn get(
+            &self,
+            _client: &Client,
+            _url: &str,
+            _query: &[(&str, &str)],
+            _limits: Limits,
+        ) -> Result<UpstreamResponse> {
+            Ok(UpstreamResponse {
+                status: self.0,
+                content_type: Some(self.1.to_string()),
+                body: self.2.clone(),
+            })
+        }
+    }
+
+    #[test]
+    fn replay_serves_recorded_exchanges_with_current_callback() {
+        let dir = std::env::temp_dir().join(format!("gt-replay-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let client = Client::new();
+        let url = "https://api.geetest.com/get.php";
+
+        let recorder = RecordingTransport::new(Arc::new(StubTransport), dir.clone());
+        recorder
+            .get(
+                &client,
+                url,
+                &[("gt", "g"), ("callback", "geetest_1")],
+                Expected::Json.limits(),
+            )
+            .unwrap();
+        assert!(dir.join("000.json").exists());
+        assert!(dir.join("000.txt").exists());
+
+        let replay = ReplayTransport::load(&dir).unwrap();
+        let res = replay
+            .get(
+                &client,
+                url,
+                &[("gt", "g"), ("callback", "geetest_2")],
+                Expected::Json.limits(),
+            )
+            .unwrap();
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body, br#"geetest_2({"status": "success"})"#);
+        assert!(replay
+            .get(&client, url, &[], Expected::Json.limits())
+            .is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fetch_rejects_error_pages_and_oversized_images() {
+        let client = Client::new();
+        let url = "https://static.geetest.com/pictures/gt/a/b.jpg";
+        let png = {
+            let mut buf = std::io::Cursor::new(Vec::new());
+            image::DynamicImage::new_rgb8(1, 1)
+                .write_to(&mut buf, image::ImageFormat::Png)
+                .unwrap();
+            buf.into_inner()
+        };
+
+        let not_found = CannedTransport(404, "text/html", b"<html>404</html>".to_vec());
+        let Err(err) = fetch(&not_found, &client, url, &[], Expected::Image) else {
+            panic!("404 页面不应被当作图片");
+        };
+        assert_eq!(err.code(), Some(crate::error::UPSTREAM_ERROR));
+        let message = err.to_string();
+        assert!(message.contains("404") && message.contains("static.geetest.com"));
+        assert!(message.contains("/pictures/gt/a/b.jpg"));
+
+        let html = CannedTransport(200, "text/html", b"<html>blocked</html>".to_vec());
+        assert!(fetch(&html, &client, url, &[], Expected::Image).is_err());
+
+        // 没有正确 Content-Type 时按文件头识别
+        let octet = CannedTransport(200, "application/octet-stream", png.clone());
+        assert!(fetch(&octet, &client, url, &[], Expected::Image).is_ok());
+
+        // 大小上限显式传入，不改动全局配置，避免影响并行执行的其他测试
+        let oversized = CannedTransport(200, "image/png", png.clone());
+        let limits = Limits {
+            max_bytes: png.len() as u64 - 1,
+            timeout: None,
+        };
+        let Err(err) = fetch_with_limits(&oversized, &client, url, &[], Expected::Image, limits)
+        else {
+            panic!("超过大小上限的图片应被拒绝");
+        };
+        assert!(err.to_string().contains("字节上限"));
+        assert!(fetch(&oversized, &client, url, &[], Expected::Image).is_ok());
+    }
+}
@@ -1,10 +1,11 @@
-use std::collections::HashSet;
-use rand::{random, thread_rng, Rng};
-use rsa::{BigUint, RsaPublicKey, Pkcs1v15Encrypt};
-use rand::rngs::OsRng;
-use serde_json::{json};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use rsa::{BigUint, Pkcs1v15Encrypt, RsaPublicKey};
+use serde_json::json;
 use soft_aes::aes::aes_enc_cbc;
+use std::collections::HashSet;
 
+use crate::entropy::{Entropy, SecureRng};
 use crate::error::{other, other_without_source, Result};
 
 const RSA_N: &str = "00C1E3934D1614465B33053E7F48EE4EC87B14B95EF88947713D25EECBFF7E74C7977D02DC1D9451F79DD5D1C10C29ACB6A9B4D6FB7D0A0279B6719E1772565F09AF627715919221AEF91899CAE08C0D686D748B20A3603BE2318CA6BC2B59706592A9219D0BF05C9F65023A21D2330807252AE0066D59CEEFA5F2748EA80BAB81";
@@ -12,22 +13,25 @@ const RSA_E: &str = "010001";
 const AES_KEY: &str = "1234567890123456";
 const AES_IV: [u8; 16] = [48u8; 16];
 
-
 const BASE64_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789()";
 const MASK1: i32 = 7274496;
 const MASK2: i32 = 9483264;
 const MASK3: i32 = 19220;
 const MASK4: i32 = 235;
 
-#[inline(always)]
-fn choose_bit(base: i32, bit: i32) -> i32 {
+/// 滑块背景图宽度，滑动距离不会超过它
+const MAX_SLIDE_DISTANCE: i32 = 260;
+
+const fn choose_bit(base: i32, bit: i32) -> i32 {
     (base >> bit) & 1
 }
 
-#[inline(always)]
-fn get_int_by_mask(base: i32, mask: i32) -> i32 {
+/// 按 mask 中为 1 的位从高到低取出 base 的对应位
+const fn get_int_by_mask(base: i32, mask: i32) -> i32 {
     let mut res = 0;
-    for bit in (0..24).rev() {
+    let mut bit = 24;
+    while bit > 0 {
+        bit -= 1;
         if choose_bit(mask, bit) == 1 {
             res = (res << 1) | choose_bit(base, bit)
         }
@@ -35,64 +39,71 @@ fn get_int_by_mask(base: i32, mask: i32) -> i32 {
     res
 }
 
-pub fn base64(input: &[u8]) -> String {
-    let input = input.iter().map(|x| *x as i32).collect::<Vec<i32>>();
-    let mut result: String = String::new();
-    let mut padding = "";
-    let len = input.len();
-    let mut ptr = 0;
-    while ptr < len {
-        if ptr + 2 < len {
-            let c: i32 = (input[ptr] << 16) + (input[ptr + 1] << 8) + input[ptr + 2];
-            result = format!(
-                "{}{}{}{}{}",
-                result,
-                BASE64_TABLE[get_int_by_mask(c, MASK1) as usize] as char,
-                BASE64_TABLE[get_int_by_mask(c, MASK2) as usize] as char,
-                BASE64_TABLE[get_int_by_mask(c, MASK3) as usize] as char,
-                BASE64_TABLE[get_int_by_mask(c, MASK4) as usize] as char
-            );
-        } else {
-            let u = len % 3;
-            if u == 2 {
-                let c: i32 = (input[ptr] << 16) + (input[ptr + 1] << 8);
-                result = format!(
-                    "{}{}{}{}",
-                    result,
-                    BASE64_TABLE[get_int_by_mask(c, MASK1) as usize] as char,
-                    BASE64_TABLE[get_int_by_mask(c, MASK2) as usize] as char,
-                    BASE64_TABLE[get_int_by_mask(c, MASK3) as usize] as char,
-                );
-                padding = "."
-            } else if u == 1 {
-                let c: i32 = input[ptr] << 16;
-                result = format!(
-                    "{}{}{}",
-                    result,
-                    BASE64_TABLE[get_int_by_mask(c, MASK1) as usize] as char,
-                    BASE64_TABLE[get_int_by_mask(c, MASK2) as usize] as char,
-                );
-                padding = ".."
+/// ### 预先算好的取位结果
+/// - `SEXTETS[k][j][b]` 为三字节分组中第 j 个字节取值 b 时对第 k 个字符的贡献
+/// - 四个 mask 互不重叠，三个字节的贡献按位或即得 `get_int_by_mask(c, MASKk)`
+static SEXTETS: [[[u8; 256]; 3]; 4] = {
+    let masks = [MASK1, MASK2, MASK3, MASK4];
+    let mut table = [[[0u8; 256]; 3]; 4];
+    let mut k = 0;
+    while k < 4 {
+        let mut j = 0;
+        while j < 3 {
+            let mut b = 0;
+            while b < 256 {
+                table[k][j][b] = get_int_by_mask((b as i32) << (16 - 8 * j), masks[k]) as u8;
+                b += 1;
             }
+            j += 1;
         }
-        ptr += 3
+        k += 1;
     }
-    format!("{}{}", result, padding)
+    table
+};
+
+/// base64 编码后的长度，不足三字节的分组用 `.` 补齐
+const fn base64_len(len: usize) -> usize {
+    len.div_ceil(3) * 4
 }
 
-fn rsa_encrypt(data: &str) -> Result<String> {
-    let n_bytes = hex::decode(RSA_N).map_err(|e| other("RSA modulus hex 解析失败", e))?;
-    let e_bytes = hex::decode(RSA_E).map_err(|e| other("RSA exponent hex 解析失败", e))?;
+/// 单独编码一段数据，生成 w 时使用 `push_base64` 直接写入预留好的缓冲区
+#[cfg(any(test, fuzzing))]
+pub fn base64(input: &[u8]) -> String {
+    let mut out = String::with_capacity(base64_len(input.len()));
+    push_base64(&mut out, input);
+    out
+}
 
-    let n = BigUint::from_bytes_be(&n_bytes);
-    let e = BigUint::from_bytes_be(&e_bytes);
+/// 把 input 的 base64 编码追加到 out 末尾，调用方负责预留容量
+fn push_base64(out: &mut String, input: &[u8]) {
+    let sextet = |k: usize, chunk: &[u8]| {
+        let value = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |value, (j, &b)| value | SEXTETS[k][j][b as usize]);
+        BASE64_TABLE[value as usize] as char
+    };
+    for chunk in input.chunks(3) {
+        // n 个字节对应 n + 1 个字符
+        for k in 0..=chunk.len() {
+            out.push(sextet(k, chunk));
+        }
+        for _ in chunk.len()..3 {
+            out.push('.');
+        }
+    }
+}
 
-    let pub_key =
-        RsaPublicKey::new(n, e).map_err(|e| other("RSA 公钥构建失败", e))?;
+/// 内置公钥只解析一次
+static RSA_PUBLIC_KEY: Lazy<RsaPublicKey> = Lazy::new(|| {
+    let n = BigUint::from_bytes_be(&hex::decode(RSA_N).expect("RSA modulus hex 解析失败"));
+    let e = BigUint::from_bytes_be(&hex::decode(RSA_E).expect("RSA exponent hex 解析失败"));
+    RsaPublicKey::new(n, e).expect("RSA 公钥构建失败")
+});
 
+fn rsa_encrypt(data: &str, mut rng: &mut dyn SecureRng) -> Result<String> {
     let padding = Pkcs1v15Encrypt;
-    let mut rng = OsRng;
-    let encrypted_data = pub_key
+    let encrypted_data = RSA_PUBLIC_KEY
         .encrypt(&mut rng, padding, data.as_bytes())
         .map_err(|e| other("RSA 加密失败", e))?;
 
@@ -100,28 +111,45 @@ fn rsa_encrypt(data: &str) -> Result<String> {
 }
 
 fn aes_encrypt(data: &str) -> Result<Vec<u8>> {
-    let encrypted =
-        aes_enc_cbc(data.as_bytes(), AES_KEY.as_bytes(), &AES_IV, Some("PKCS7"))
-            .map_err(|e| other_without_source(&format!("AES 加密失败: {e}")))?;
+    let encrypted = aes_enc_cbc(data.as_bytes(), AES_KEY.as_bytes(), &AES_IV, Some("PKCS7"))
+        .map_err(|e| other_without_source(&format!("AES 加密失败: {e}")))?;
     Ok(encrypted)
 }
 
-fn encrypt(json_str: &str) -> Result<String> {
-    let u = rsa_encrypt(AES_KEY)?;
+fn encrypt(json_str: &str, rng: &mut dyn SecureRng) -> Result<String> {
+    let u = rsa_encrypt(AES_KEY, rng)?;
     let h = aes_encrypt(json_str)?;
-    let p = base64(h.as_ref());
-    Ok(format!("{}{}", p, u))
+    // 一次预留出 base64 密文和 RSA 密文的总长度
+    let mut w = String::with_capacity(base64_len(h.len()) + u.len());
+    push_base64(&mut w, &h);
+    w.push_str(&u);
+    Ok(w)
 }
 
-fn get_random_webgl() -> (&'static str, &'static str) {
+/// 去掉 challenge 末尾两个字符，challenge 来自调用方，按字符切分以免越界或截断多字节字符
+fn challenge_prefix(challenge: &str) -> Result<&str> {
+    challenge
+        .char_indices()
+        .rev()
+        .nth(1)
+        .map(|(idx, _)| &challenge[..idx])
+        .ok_or_else(|| other_without_source("challenge 长度不足"))
+}
+
+fn get_random_webgl(rng: &mut dyn SecureRng) -> (&'static str, &'static str) {
     static WEBGL_DATA: &str = include_str!("../data.txt");
-    
-    let lines = WEBGL_DATA.lines().filter(|l| !l.is_empty()).collect::<Vec<_>>();
+
+    let lines = WEBGL_DATA
+        .lines()
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
     if lines.is_empty() {
-        return ("Google Inc. (Intel)", "ANGLE (Intel, Intel(R) HD Graphics 520 Direct3D11 vs_5_0 ps_5_0, D3D11)");
+        return (
+            "Google Inc. (Intel)",
+            "ANGLE (Intel, Intel(R) HD Graphics 520 Direct3D11 vs_5_0 ps_5_0, D3D11)",
+        );
     }
 
-    let mut rng = thread_rng();
     let idx = rng.gen_range(0..lines.len());
     let selected_line = lines[idx];
 
@@ -136,22 +164,32 @@ fn get_random_webgl() -> (&'static str, &'static str) {
     if let Some((ven, ren)) = rest.split_once(',') {
         (ven.trim(), ren.trim())
     } else {
-        ("Google Inc. (Intel)", "ANGLE (Intel, Intel(R) HD Graphics 520 Direct3D11 vs_5_0 ps_5_0, D3D11)")
+        (
+            "Google Inc. (Intel)",
+            "ANGLE (Intel, Intel(R) HD Graphics 520 Direct3D11 vs_5_0 ps_5_0, D3D11)",
+        )
     }
 }
 
-pub(crate) fn click_calculate(key: &str, gt: &str, challenge: &str) -> Result<String> {
-    let pass_time = (random::<f32>() * 700f32 + 1300f32) as usize;
-    let m5 = md5::compute(format!("{}{}{}", gt, &challenge[..challenge.len()-2].to_string(), pass_time));
+pub(crate) fn click_calculate(
+    key: &str,
+    gt: &str,
+    challenge: &str,
+    entropy: &Entropy,
+) -> Result<String> {
+    let mut rng = entropy.rng.fork();
+    let pass_time = (rng.gen::<f32>() * 700f32 + 1300f32) as usize;
+    let m5 = md5::compute(format!(
+        "{}{}{}",
+        gt,
+        challenge_prefix(challenge)?,
+        pass_time
+    ));
     let rp = hex::encode(m5.to_vec());
 
-    let now_ms = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64;
+    let now_ms = entropy.clock.now_ms();
 
-    let (ven, ren) = get_random_webgl();
-    let mut rng = thread_rng();
+    let (ven, ren) = get_random_webgl(rng.as_mut());
 
     let dic = json!({
         "lang": "zh-cn",
@@ -205,16 +243,18 @@ pub(crate) fn click_calculate(key: &str, gt: &str, challenge: &str) -> Result<St
         "rp": rp,
     });
 
-    encrypt(dic.to_string().as_str())
+    encrypt(dic.to_string().as_str(), rng.as_mut())
 }
 
-fn get_slide_track(distance: i32) -> Result<Vec<Vec<i32>>> {
+fn get_slide_track(distance: i32, rng: &mut dyn SecureRng) -> Result<Vec<Vec<i32>>> {
     if distance < 0 {
         return Err(other_without_source("滑动距离必须大于等于0"));
     }
+    if distance > MAX_SLIDE_DISTANCE {
+        return Err(other_without_source("滑动距离超出背景图宽度"));
+    }
 
     let mut slide_track = Vec::new();
-    let mut rng = thread_rng();
 
     // 初始化轨迹列表
     let x1 = rng.gen_range(-50..=-10); // 生成-50到-10之间的整数
@@ -260,28 +300,26 @@ fn get_slide_track(distance: i32) -> Result<Vec<Vec<i32>>> {
     Ok(slide_track)
 }
 
-fn track_encrypt(track: &Vec<Vec<i32>>) -> String {
+fn track_encrypt(track: &[Vec<i32>]) -> String {
     // 轨迹处理函数
+    // 差值使用饱和运算，超出编码范围的值在 encode_value 中本来就会被截断
     fn process_track(track: &[Vec<i32>]) -> Vec<Vec<i32>> {
         let mut result = Vec::new();
-        let mut o = 0;
-        let mut e;
-        let mut n;
-        let mut r;
+        let mut o: i32 = 0;
 
-        for s in 0..track.len() - 1 {
-            e = track[s + 1][0] - track[s][0];
-            n = track[s + 1][1] - track[s][1];
-            r = track[s + 1][2] - track[s][2];
+        for pair in track.windows(2) {
+            let e = pair[1][0].saturating_sub(pair[0][0]);
+            let n = pair[1][1].saturating_sub(pair[0][1]);
+            let r = pair[1][2].saturating_sub(pair[0][2]);
 
             if e == 0 && n == 0 && r == 0 {
                 continue;
             }
 
             if e == 0 && n == 0 {
-                o += r;
+                o = o.saturating_add(r);
             } else {
-                result.push(vec![e, n, r + o]);
+                result.push(vec![e, n, r.saturating_add(o)]);
                 o = 0;
             }
         }
@@ -300,16 +338,12 @@ fn track_encrypt(track: &Vec<Vec<i32>>) -> String {
     // 数值编码函数
     fn encode_value(t: i32) -> String {
         let e = "()*,-./0123456789:?@ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqr";
-        let n = e.len() as i32;
+        let n = e.len();
         let mut r = String::new();
 
         let i = t.unsigned_abs() as usize;
-        let o_val = i as i32 / n;
-        let mut o = if o_val >= n { n - 1 } else { o_val } as usize;
-
-        if o >= e.len() {
-            o = e.len() - 1;
-        }
+        // 超过两位的值截断为最大的高位，与原版 JS 一致
+        let o = (i / n).min(n - 1);
 
         if o > 0 {
             r.push(e.chars().nth(o).unwrap());
@@ -323,18 +357,27 @@ fn track_encrypt(track: &Vec<Vec<i32>>) -> String {
             s.push('$');
         }
 
-        s + &r + &e.chars().nth(i % n as usize).unwrap().to_string()
+        s + &r + &e.chars().nth(i % n).unwrap().to_string()
     }
 
     // 特殊模式编码
     fn encode_pair(t: &[i32]) -> Option<char> {
         let pairs = [
-            [1, 0], [2, 0], [1, -1], [1, 1], [0, 1],
-            [0, -1], [3, 0], [2, -1], [2, 1]
+            [1, 0],
+            [2, 0],
+            [1, -1],
+            [1, 1],
+            [0, 1],
+            [0, -1],
+            [3, 0],
+            [2, -1],
+            [2, 1],
         ];
         let chars = "stuvwxyz~";
 
-        pairs.iter().enumerate()
+        pairs
+            .iter()
+            .enumerate()
             .find(|(_, pair)| t[0] == pair[0] && t[1] == pair[1])
             .map(|(i, _)| chars.chars().nth(i).unwrap())
     }
@@ -363,40 +406,42 @@ fn track_encrypt(track: &Vec<Vec<i32>>) -> String {
     format!("{}!!{}!!{}", r, i, o)
 }
 
-fn final_encrypt(t: String, e: &[u8], n: String) -> String {
-    if e.len() < 5 || n.is_empty() {
-        return t;
+/// ### 把 s 参数逐字节插入轨迹密文
+/// - 与原版 JS 一致：按字符位置插入，位置对原始长度取模，末尾不足两位的十六进制也会插入
+/// - s 来自调用方，不是十六进制时返回错误
+fn final_encrypt(t: String, e: &[u8], n: String) -> Result<String> {
+    if e.len() < 5 || n.is_empty() || t.is_empty() {
+        return Ok(t);
     }
 
-    let s = e[0];
-    let a = e[2];
-    let m = e[4];
-
-    let original_len = t.len(); // 固定使用原始长度
-    let mut i = 0;
-    let mut o = t.clone();
+    let s = e[0] as u64;
+    let a = e[2] as u64;
+    let m = e[4] as u64;
 
-    while i <= n.len() - 2 {
-        let r = &n[i..i + 2];
-        i += 2;
+    let original_len = t.chars().count() as u64; // 固定使用原始长度
+    let mut o: Vec<char> = t.chars().collect();
 
-        let c = u8::from_str_radix(r, 16).unwrap();
-        let u = char::from(c);
+    for r in n.as_bytes().chunks(2) {
+        let c = std::str::from_utf8(r)
+            .ok()
+            .and_then(|r| u8::from_str_radix(r, 16).ok())
+            .ok_or_else(|| other_without_source("s 参数不是十六进制"))?;
+        let c64 = c as u64;
 
         // 基于原始长度计算插入位置
-        let ll = (s as u64 * c as u64 * c as u64 + a as u64 * c as u64 + m as u64) % original_len as u64;
-        let ll = ll as usize;
-
-        o.insert(ll, u);
+        let ll = (s * c64 * c64 + a * c64 + m) % original_len;
+        o.insert(ll as usize, char::from(c));
     }
 
-    o
+    Ok(o.into_iter().collect())
 }
 
-fn user_response(key: i32, challenge: &str) -> String {
+fn user_response(key: i32, challenge: &str) -> Result<String> {
     // 处理最后两个字符
     let chars_e: Vec<char> = challenge.chars().collect();
-    assert!(chars_e.len() >= 2, "输入字符串 e 必须至少包含两个字符");
+    if chars_e.len() < 2 {
+        return Err(other_without_source("challenge 长度不足"));
+    }
     let n_chars = &chars_e[chars_e.len() - 2..];
 
     // 计算 r 数组
@@ -404,18 +449,24 @@ fn user_response(key: i32, challenge: &str) -> String {
         .iter()
         .map(|&c| {
             let code = c as i32;
-            if code > 57 { code - 87 } else { code - 48 }
+            if code > 57 {
+                code - 87
+            } else {
+                code - 48
+            }
         })
         .collect();
     let n = 36 * r[0] + r[1];
 
     // 计算初始值 a
-    let a = key + n;
+    let a = key
+        .checked_add(n)
+        .ok_or_else(|| other_without_source("滑动距离超出范围"))?;
 
     // 初始化数据结构
     let mut underscores = vec![vec![]; 5]; // 五元组数组
-    let mut char_set = HashSet::new();     // 字符去重集合
-    let mut idx = 0;                       // 轮询下标
+    let mut char_set = HashSet::new(); // 字符去重集合
+    let mut idx = 0; // 轮询下标
 
     // 处理主字符串（去掉最后两个字符）
     let processed_e: String = chars_e[..chars_e.len() - 2].iter().collect();
@@ -436,57 +487,58 @@ fn user_response(key: i32, challenge: &str) -> String {
     let mut weights = vec![1, 2, 5, 10, 50]; // 权重数组
 
     while f > 0 {
-        // 处理下标越界情况（理论上不会出现）
-        if d >= weights.len() {
-            panic!("权重数组越界，请检查输入参数有效性");
-        }
-
         if f >= weights[d] {
             // 安全访问第一个元素
-            if let Some(&char) = underscores[d].first() {
-                result.push(char);
-                f -= weights[d];
-            } else {
-                panic!("五元组数组 {} 号位置无可用字符，请确保输入字符串包含足够多的唯一字符", d);
-            }
+            let char = underscores[d].first().ok_or_else(|| {
+                other_without_source(&format!(
+                    "五元组数组 {} 号位置无可用字符，challenge 中的不同字符太少",
+                    d
+                ))
+            })?;
+            result.push(*char);
+            f -= weights[d];
         } else {
-            // 移除当前权重并下移指针
+            // 移除当前权重并下移指针，权重 1 总能满足 f > 0，d 不会减到 0 以下
             underscores.remove(d);
             weights.remove(d);
-            if d > 0 {
-                d -= 1;
-            } else {
-                panic!("权重数组耗尽，无法继续处理");
-            }
+            d -= 1;
         }
     }
 
-    result
+    Ok(result)
 }
 
-
-pub fn slide_calculate(key: i32, gt: &str, challenge: &str, c: &[u8], s: &str) -> Result<String> {
-    let track = get_slide_track(key)?;
+pub fn slide_calculate(
+    key: i32,
+    gt: &str,
+    challenge: &str,
+    c: &[u8],
+    s: &str,
+    entropy: &Entropy,
+) -> Result<String> {
+    let mut rng = entropy.rng.fork();
+    let track = get_slide_track(key, rng.as_mut())?;
     let pass_time = track
         .last()
         .ok_or_else(|| other_without_source("滑动轨迹为空"))?[2];
     let aa = {
         let encrypted_track = track_encrypt(&track);
-        final_encrypt(encrypted_track, c, s.to_string())
+        final_encrypt(encrypted_track, c, s.to_string())?
     };
 
-    let user_response = user_response(key, challenge);
+    let user_response = user_response(key, challenge)?;
 
-    let m5 = md5::compute(format!("{}{}{}", gt, &challenge[..challenge.len() - 2], pass_time));
+    let m5 = md5::compute(format!(
+        "{}{}{}",
+        gt,
+        challenge_prefix(challenge)?,
+        pass_time
+    ));
     let rp = hex::encode(m5.to_vec());
 
-    let now_ms = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64;
+    let now_ms = entropy.clock.now_ms();
 
-    let (ven, ren) = get_random_webgl();
-    let mut rng = thread_rng();
+    let (ven, ren) = get_random_webgl(rng.as_mut());
 
     let dic = json!({
         "lang": "zh-cn",
@@ -539,5 +591,437 @@ pub fn slide_calculate(key: i32, gt: &str, challenge: &str, c: &[u8], s: &str) -
         },
         "rp": rp,
     });
-    encrypt(dic.to_string().as_str())
+    encrypt(dic.to_string().as_str(), rng.as_mut())
+}
+
+/// ### 还原自己生成的 w，仅供测试和模糊测试使用
+/// - w 由自定义 base64 编码的 AES 密文和 RSA 加密的 AES 密钥拼接而成，AES 密钥固定，可以直接解出原始 JSON
+#[cfg(any(test, fuzzing))]
+pub(crate) mod decode {
+    use super::*;
+    use serde_json::Value;
+    use soft_aes::aes::aes_dec_cbc;
+    use std::iter::Peekable;
+    use std::str::Chars;
+
+    /// track_encrypt 中 encode_value 的字符表
+    const TRACK_TABLE: &str = "()*,-./0123456789:?@ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqr";
+    /// track_encrypt 中 encode_pair 的特殊位移
+    const TRACK_PAIRS: [(char, [i32; 2]); 9] = [
+        ('s', [1, 0]),
+        ('t', [2, 0]),
+        ('u', [1, -1]),
+        ('v', [1, 1]),
+        ('w', [0, 1]),
+        ('x', [0, -1]),
+        ('y', [3, 0]),
+        ('z', [2, -1]),
+        ('~', [2, 1]),
+    ];
+
+    /// RSA 部分为 1024 位密文的十六进制
+    const RSA_HEX_LEN: usize = 256;
+
+    /// 把 value 的各位依次放回 mask 中为 1 的位置，是 get_int_by_mask 的逆运算
+    fn set_int_by_mask(value: i32, mask: i32) -> i32 {
+        let mut remaining = mask.count_ones() as i32;
+        let mut res = 0;
+        for bit in (0..24).rev() {
+            if choose_bit(mask, bit) == 1 {
+                remaining -= 1;
+                res |= choose_bit(value, remaining) << bit;
+            }
+        }
+        res
+    }
+
+    /// `base64` 的逆运算
+    pub(crate) fn unbase64(input: &str) -> Result<Vec<u8>> {
+        let body = input.trim_end_matches('.');
+        let values = body
+            .bytes()
+            .map(|b| {
+                BASE64_TABLE
+                    .iter()
+                    .position(|&c| c == b)
+                    .map(|v| v as i32)
+                    .ok_or_else(|| {
+                        other_without_source(&format!("非法的 base64 字符: {}", b as char))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let masks = [MASK1, MASK2, MASK3, MASK4];
+        let mut output = Vec::with_capacity(values.len() * 3 / 4);
+        for chunk in values.chunks(4) {
+            let c = chunk
+                .iter()
+                .zip(masks)
+                .fold(0, |c, (&v, mask)| c | set_int_by_mask(v, mask));
+            let bytes = [(c >> 16) as u8, (c >> 8) as u8, c as u8];
+            match chunk.len() {
+                4 => output.extend_from_slice(&bytes),
+                3 => output.extend_from_slice(&bytes[..2]),
+                2 => output.push(bytes[0]),
+                _ => return Err(other_without_source("base64 长度不正确")),
+            }
+        }
+        Ok(output)
+    }
+
+    /// 解出 w 中的 JSON
+    pub(crate) fn payload(w: &str) -> Result<Value> {
+        let aes_part = w
+            .len()
+            .checked_sub(RSA_HEX_LEN)
+            .map(|end| &w[..end])
+            .ok_or_else(|| other_without_source("w 长度不足"))?;
+        let cipher = unbase64(aes_part)?;
+        let plain = aes_dec_cbc(&cipher, AES_KEY.as_bytes(), &AES_IV, Some("PKCS7"))
+            .map_err(|e| other_without_source(&format!("AES 解密失败: {e}")))?;
+        serde_json::from_slice(&plain).map_err(crate::error::parse_error)
+    }
+
+    /// 读取 encode_value 编码的一个值，绝对值不小于 65 * 65 的值编码时已被截断
+    fn track_value(chars: &mut Peekable<Chars>) -> Option<i32> {
+        let negative = chars.next_if_eq(&'!').is_some();
+        let high = match chars.next_if_eq(&'$') {
+            Some(_) => TRACK_TABLE.find(chars.next()?)?,
+            None => 0,
+        };
+        let value = (high * TRACK_TABLE.len() + TRACK_TABLE.find(chars.next()?)?) as i32;
+        Some(if negative { -value } else { value })
+    }
+
+    /// `track_encrypt` 的逆运算，返回相邻轨迹点之间的位移和耗时
+    pub(crate) fn track(encoded: &str) -> Option<Vec<[i32; 3]>> {
+        let mut parts = encoded.splitn(3, "!!");
+        let mut r = parts.next()?.chars().peekable();
+        let mut i = parts.next()?.chars().peekable();
+        let mut o = parts.next()?.chars().peekable();
+        let mut result = Vec::new();
+        while let Some(c) = i.peek() {
+            let [e, n] = match TRACK_PAIRS.iter().find(|(pair, _)| pair == c) {
+                Some((_, pair)) => {
+                    i.next();
+                    *pair
+                }
+                None => [track_value(&mut r)?, track_value(&mut i)?],
+            };
+            result.push([e, n, track_value(&mut o)?]);
+        }
+        (r.next().is_none() && o.next().is_none()).then_some(result)
+    }
+
+    /// `user_response` 的逆运算，返回滑动距离
+    pub(crate) fn user_response_key(response: &str, challenge: &str) -> i32 {
+        let chars: Vec<char> = challenge.chars().collect();
+        let (body, tail) = chars.split_at(chars.len() - 2);
+        let r: Vec<i32> = tail
+            .iter()
+            .map(|&c| {
+                if c as i32 > 57 {
+                    c as i32 - 87
+                } else {
+                    c as i32 - 48
+                }
+            })
+            .collect();
+        let mut seen = HashSet::new();
+        let mut firsts: Vec<Option<char>> = vec![None; 5];
+        for (k, &c) in body.iter().filter(|&&c| seen.insert(c)).enumerate() {
+            firsts[k % 5].get_or_insert(c);
+        }
+        let weights = [1, 2, 5, 10, 50];
+        let total: i32 = response
+            .chars()
+            .map(|c| {
+                let idx = firsts.iter().position(|&f| f == Some(c)).unwrap();
+                weights[idx]
+            })
+            .sum();
+        total - (36 * r[0] + r[1])
+    }
+}
+
+/// ### cargo-fuzz 入口
+/// - 由 fuzz 目录下的目标调用，检查各编码器不会 panic，并校验能校验的不变量
+#[cfg(fuzzing)]
+pub mod fuzz {
+    use super::decode;
+
+    pub fn base64(data: &[u8]) {
+        let encoded = super::base64(data);
+        assert_eq!(encoded.len(), data.len().div_ceil(3) * 4);
+        assert_eq!(decode::unbase64(&encoded).unwrap(), data);
+    }
+
+    pub fn track_encrypt(track: &[[i32; 3]]) {
+        let rows = track.iter().map(|row| row.to_vec()).collect::<Vec<_>>();
+        let encoded = super::track_encrypt(&rows);
+        assert!(
+            decode::track(&encoded).is_some(),
+            "无法解析的轨迹密文: {encoded}"
+        );
+    }
+
+    pub fn final_encrypt(t: &str, e: &[u8], n: &str) {
+        if let Ok(output) = super::final_encrypt(t.to_string(), e, n.to_string()) {
+            let inserted = if e.len() < 5 || t.is_empty() {
+                0
+            } else {
+                n.len().div_ceil(2)
+            };
+            assert_eq!(output.chars().count(), t.chars().count() + inserted);
+        }
+    }
+
+    pub fn user_response(key: i16, challenge: &str) {
+        if let Ok(response) = super::user_response(key as i32, challenge) {
+            if !response.is_empty() {
+                assert_eq!(decode::user_response_key(&response, challenge), key as i32);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entropy::{FixedClock, RngSource, SeededRngSource};
+    use proptest::prelude::*;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    const CHALLENGE: &str = "0123456789abcdef0123456789abcdefgh";
+
+    fn seeded(seed: u64) -> Entropy {
+        Entropy {
+            rng: Arc::new(SeededRngSource::new(seed)),
+            clock: Arc::new(FixedClock(1_700_000_000_000)),
+        }
+    }
+
+    #[test]
+    fn seeded_entropy_reproduces_w() {
+        let click = |seed| click_calculate("4231_5722,7023_2812", "gt", CHALLENGE, &seeded(seed));
+        assert_eq!(click(7).unwrap(), click(7).unwrap());
+        assert_ne!(click(7).unwrap(), click(8).unwrap());
+
+        let slide =
+            |seed| slide_calculate(120, "gt", CHALLENGE, &[1, 2, 3, 4, 5], "ab", &seeded(seed));
+        assert_eq!(slide(7).unwrap(), slide(7).unwrap());
+
+        // 同一来源按顺序派生的生成器各不相同
+        let source = SeededRngSource::new(7);
+        let first = get_slide_track(120, source.fork().as_mut()).unwrap();
+        let second = get_slide_track(120, source.fork().as_mut()).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(
+            first,
+            get_slide_track(120, SeededRngSource::new(7).fork().as_mut()).unwrap()
+        );
+    }
+
+    /// 改写前的实现，用来确认改写后输出逐字节相同
+    mod reference {
+        use super::*;
+
+        pub(super) fn base64(input: &[u8]) -> String {
+            let input = input.iter().map(|x| *x as i32).collect::<Vec<i32>>();
+            let mut result: String = String::new();
+            let mut padding = "";
+            let len = input.len();
+            let mut ptr = 0;
+            while ptr < len {
+                if ptr + 2 < len {
+                    let c: i32 = (input[ptr] << 16) + (input[ptr + 1] << 8) + input[ptr + 2];
+                    result = format!(
+                        "{}{}{}{}{}",
+                        result,
+                        BASE64_TABLE[get_int_by_mask(c, MASK1) as usize] as char,
+                        BASE64_TABLE[get_int_by_mask(c, MASK2) as usize] as char,
+                        BASE64_TABLE[get_int_by_mask(c, MASK3) as usize] as char,
+                        BASE64_TABLE[get_int_by_mask(c, MASK4) as usize] as char
+                    );
+                } else {
+                    let u = len % 3;
+                    if u == 2 {
+                        let c: i32 = (input[ptr] << 16) + (input[ptr + 1] << 8);
+                        result = format!(
+                            "{}{}{}{}",
+                            result,
+                            BASE64_TABLE[get_int_by_mask(c, MASK1) as usize] as char,
+                            BASE64_TABLE[get_int_by_mask(c, MASK2) as usize] as char,
+                            BASE64_TABLE[get_int_by_mask(c, MASK3) as usize] as char,
+                        );
+                        padding = "."
+                    } else if u == 1 {
+                        let c: i32 = input[ptr] << 16;
+                        result = format!(
+                            "{}{}{}",
+                            result,
+                            BASE64_TABLE[get_int_by_mask(c, MASK1) as usize] as char,
+                            BASE64_TABLE[get_int_by_mask(c, MASK2) as usize] as char,
+                        );
+                        padding = ".."
+                    }
+                }
+                ptr += 3
+            }
+            format!("{}{}", result, padding)
+        }
+
+        pub(super) fn encrypt(json_str: &str, mut rng: &mut dyn SecureRng) -> String {
+            let n = BigUint::from_bytes_be(&hex::decode(RSA_N).unwrap());
+            let e = BigUint::from_bytes_be(&hex::decode(RSA_E).unwrap());
+            let pub_key = RsaPublicKey::new(n, e).unwrap();
+            let u = pub_key
+                .encrypt(&mut rng, Pkcs1v15Encrypt, AES_KEY.as_bytes())
+                .unwrap();
+            let h = aes_encrypt(json_str).unwrap();
+            format!("{}{}", base64(h.as_ref()), hex::encode(u))
+        }
+    }
+
+    #[test]
+    fn encrypt_matches_reference_implementation() {
+        let json = json!({"lang": "zh-cn", "passtime": 1500, "a": "4231_5722"}).to_string();
+        let source = SeededRngSource::new(7);
+        let expected = reference::encrypt(&json, source.fork().as_mut());
+        let source = SeededRngSource::new(7);
+        assert_eq!(encrypt(&json, source.fork().as_mut()).unwrap(), expected);
+
+        // 容量一次预留到位，追加 RSA 密文时不再扩容；实际的明文含轨迹等字段，长度与此相当
+        let json = json!({"a": "4231_5722".repeat(100)}).to_string();
+        let w = encrypt(&json, SeededRngSource::new(7).fork().as_mut()).unwrap();
+        assert_eq!(w.capacity(), w.len());
+    }
+
+    #[test]
+    fn unbase64_inverts_base64() {
+        for len in 0..10 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + 200) as u8).collect();
+            assert_eq!(decode::unbase64(&base64(&data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn decoded_payload_matches_inputs() {
+        let rp = |pass_time: &Value| {
+            let challenge = &CHALLENGE[..CHALLENGE.len() - 2];
+            format!("{:x}", md5::compute(format!("gt{challenge}{pass_time}")))
+        };
+
+        let w = click_calculate("4231_5722,7023_2812", "gt", CHALLENGE, &seeded(7)).unwrap();
+        let payload = decode::payload(&w).unwrap();
+        assert_eq!(payload["a"], "4231_5722,7023_2812");
+        assert_eq!(payload["rp"], rp(&payload["passtime"]));
+
+        let w = slide_calculate(120, "gt", CHALLENGE, &[1, 2, 3, 4, 5], "ab", &seeded(7)).unwrap();
+        let payload = decode::payload(&w).unwrap();
+        // slide_calculate 从新的种子来源取第一个生成器，轨迹可以原样重建
+        let track = get_slide_track(120, SeededRngSource::new(7).fork().as_mut()).unwrap();
+        assert_eq!(payload["passtime"], track.last().unwrap()[2]);
+        assert_eq!(payload["rp"], rp(&payload["passtime"]));
+        let response = payload["userresponse"].as_str().unwrap();
+        assert_eq!(decode::user_response_key(response, CHALLENGE), 120);
+    }
+
+    /// 模糊测试和属性测试发现过的输入，以前会 panic 或得到与 JS 不一致的结果
+    #[test]
+    fn encoders_reject_malformed_input_instead_of_panicking() {
+        assert_eq!(track_encrypt(&[]), "!!!!");
+        assert_eq!(
+            track_encrypt(&[vec![i32::MIN, 0, 0], vec![i32::MAX, 0, 0]]),
+            "$rp!!(!!("
+        );
+
+        // 非 ASCII 字符按字符位置插入，末尾单个十六进制字符也会插入
+        let output = final_encrypt("abcde".to_string(), &[1, 0, 0, 0, 1], "ff81".to_string());
+        assert_eq!(output.unwrap(), "a\u{ff}\u{81}bcde");
+        let output = final_encrypt("abcde".to_string(), &[1, 0, 0, 0, 1], "ff8".to_string());
+        assert_eq!(output.unwrap(), "\u{8}a\u{ff}bcde");
+        assert!(final_encrypt("abcde".to_string(), &[1, 0, 0, 0, 1], "zz".to_string()).is_err());
+
+        assert!(user_response(100, "a").is_err());
+        assert!(user_response(100, "aaaaaaaagh").is_err());
+        assert!(user_response(i32::MAX, CHALLENGE).is_err());
+
+        assert_eq!(challenge_prefix("ab€cd").unwrap(), "ab€");
+        assert!(click_calculate("1_1", "gt", "é", &seeded(7)).is_err());
+        let mut rng = SeededRngSource::new(7).fork();
+        assert!(get_slide_track(MAX_SLIDE_DISTANCE + 1, rng.as_mut()).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn base64_round_trips(data in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let encoded = base64(&data);
+            prop_assert_eq!(&encoded, &reference::base64(&data));
+            prop_assert_eq!(encoded.len(), data.len().div_ceil(3) * 4);
+            prop_assert_eq!(decode::unbase64(&encoded).unwrap(), data);
+        }
+
+        /// 每一步都有位移时轨迹密文能还原出每一步的位移和耗时
+        #[test]
+        fn track_encrypt_round_trips(
+            steps in proptest::collection::vec((-4095..4096, -4095..4096, -4095..4096), 0..64)
+        ) {
+            let steps = steps
+                .into_iter()
+                .map(|(e, n, r)| if e == 0 && n == 0 { [1, n, r] } else { [e, n, r] })
+                .collect::<Vec<_>>();
+            let mut track = vec![vec![0, 0, 0]];
+            for [e, n, r] in &steps {
+                let last = track.last().unwrap();
+                track.push(vec![last[0] + e, last[1] + n, last[2] + r]);
+            }
+            prop_assert_eq!(decode::track(&track_encrypt(&track)), Some(steps));
+        }
+
+        #[test]
+        fn track_encrypt_accepts_any_coordinates(
+            track in proptest::collection::vec(proptest::collection::vec(any::<i32>(), 3), 0..16)
+        ) {
+            prop_assert!(decode::track(&track_encrypt(&track)).is_some());
+        }
+
+        /// 输出为原文插入 s 中每个字节后的结果，原文字符顺序不变
+        #[test]
+        fn final_encrypt_only_inserts(
+            t in "[ -~]{1,40}",
+            e in proptest::collection::vec(any::<u8>(), 5..8),
+            n in "[0-9a-f]{0,16}",
+        ) {
+            let output = final_encrypt(t.clone(), &e, n.clone()).unwrap();
+            prop_assert_eq!(output.chars().count(), t.chars().count() + n.len().div_ceil(2));
+            let mut rest = output.chars();
+            prop_assert!(t.chars().all(|c| rest.any(|o| o == c)));
+        }
+
+        #[test]
+        fn final_encrypt_accepts_any_s(
+            t in ".{0,8}",
+            e in proptest::collection::vec(any::<u8>(), 0..8),
+            n in ".{0,8}",
+        ) {
+            let _ = final_encrypt(t, &e, n);
+        }
+
+        #[test]
+        fn user_response_round_trips(
+            key in 0..=MAX_SLIDE_DISTANCE,
+            challenge in "[0-9a-f]{32}[0-9a-z]{2}",
+        ) {
+            let unique = challenge[..32].chars().collect::<std::collections::HashSet<_>>().len();
+            prop_assume!(unique >= 5);
+            let response = user_response(key, &challenge).unwrap();
+            prop_assert_eq!(decode::user_response_key(&response, &challenge), key);
+        }
+
+        #[test]
+        fn user_response_accepts_any_challenge(key in -1000..1000i32, challenge in ".{0,40}") {
+            let _ = user_response(key, &challenge);
+        }
+    }
 }